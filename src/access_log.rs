@@ -0,0 +1,104 @@
+use http::{Method, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Random per-process salt, so anonymized visitors can't be matched across restarts
+static SALT: once_cell::sync::Lazy<[u8; 32]> =
+    once_cell::sync::Lazy::new(|| nostr_sdk::Keys::generate().secret_key().to_secret_bytes());
+
+/// How much of a visit to a site is written to the access log
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLog {
+    /// Client address, host, method, path and status
+    #[default]
+    Full,
+    /// Client address replaced with a salted hash and the path left out
+    Anonymized,
+    /// Nothing is logged
+    Off,
+}
+
+/// A finished request as seen by the access log
+pub struct Visit<'a> {
+    pub client: Option<IpAddr>,
+    pub host: &'a str,
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub status: StatusCode,
+    pub elapsed: Duration,
+}
+
+/// Access log line for a visit, `None` when the site opted out of logging
+pub fn line(mode: AccessLog, visit: &Visit) -> Option<String> {
+    let (client, path) = match mode {
+        AccessLog::Full => (
+            visit
+                .client
+                .map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+            visit.path,
+        ),
+        AccessLog::Anonymized => (visit.client.map_or_else(|| "-".to_string(), anonymize), "-"),
+        AccessLog::Off => return None,
+    };
+    Some(format!(
+        "{} {} {} {} {} {:?}",
+        client,
+        visit.host,
+        visit.method,
+        path,
+        visit.status.as_u16(),
+        visit.elapsed
+    ))
+}
+
+/// Stable pseudonym for a client address within this process
+fn anonymize(ip: IpAddr) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(*SALT);
+    hasher.update(ip.to_string());
+    format!("anon-{}", &hex::encode(hasher.finalize())[..12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visit(client: &str) -> Visit<'static> {
+        Visit {
+            client: client.parse().ok(),
+            host: "blog.example.com",
+            method: &Method::GET,
+            path: "/private/page.html",
+            status: StatusCode::OK,
+            elapsed: Duration::from_millis(3),
+        }
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            line(AccessLog::Full, &visit("203.0.113.7")).unwrap(),
+            "203.0.113.7 blog.example.com GET /private/page.html 200 3ms"
+        );
+        assert_eq!(
+            line(AccessLog::Full, &visit("")).unwrap(),
+            "- blog.example.com GET /private/page.html 200 3ms"
+        );
+        assert_eq!(line(AccessLog::Off, &visit("203.0.113.7")), None);
+
+        let anonymized = line(AccessLog::Anonymized, &visit("203.0.113.7")).unwrap();
+        assert!(!anonymized.contains("203.0.113.7"));
+        assert!(!anonymized.contains("/private"));
+        assert_eq!(
+            anonymized,
+            line(AccessLog::Anonymized, &visit("203.0.113.7")).unwrap()
+        );
+        assert_ne!(
+            anonymized,
+            line(AccessLog::Anonymized, &visit("203.0.113.8")).unwrap()
+        );
+    }
+}
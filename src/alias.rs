@@ -0,0 +1,488 @@
+use anyhow::{Context, Result, anyhow};
+use nostr_sdk::{EventId, PublicKey, RelayUrl, Url};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A static subdomain alias pointing at a site
+#[derive(Clone, Debug)]
+pub struct SiteAlias {
+    /// The owner public key
+    pub pubkey: [u8; 32],
+
+    /// Site identifier (d tag) for NIP-5A named sites
+    pub identifier: Option<String>,
+
+    /// Pin the site to a specific manifest event instead of the latest one
+    pub pin: Option<EventId>,
+
+    /// Origins the site was built for, absolute links to them are rewritten in HTML
+    pub rewrite_origins: Vec<String>,
+
+    /// Host every other host serving this site redirects to
+    pub canonical_host: Option<String>,
+
+    /// Serve the coming-soon page until the site is published
+    pub reserved: bool,
+
+    /// Keep search engines out, for staging and preview sites
+    pub noindex: bool,
+
+    /// How much of a visit to the site is written to the access log
+    pub access_log: crate::access_log::AccessLog,
+
+    /// Relays the site is resolved from instead of the gateway's, for tenants on their own
+    /// relay infrastructure
+    pub relays: Vec<RelayUrl>,
+}
+
+impl SiteAlias {
+    /// Alias for the root site of `pubkey`, with every option left at its default
+    pub fn new(pubkey: [u8; 32]) -> Self {
+        Self {
+            pubkey,
+            identifier: None,
+            pin: None,
+            rewrite_origins: Vec::new(),
+            canonical_host: None,
+            reserved: false,
+            noindex: false,
+            access_log: Default::default(),
+            relays: Vec::new(),
+        }
+    }
+}
+
+/// Alias entry as written in the alias config file
+///
+/// ```json
+/// {
+///   "blog": { "pubkey": "npub1..." },
+///   "docs-v1": { "pubkey": "npub1...", "identifier": "docs", "pin": "note1..." },
+///   "docs-*": { "pubkey": "npub1...", "identifier": "*" }
+/// }
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AliasEntry {
+    /// npub or hex encoded public key
+    pubkey: String,
+    identifier: Option<String>,
+    /// note/nevent or hex encoded manifest event id
+    pin: Option<String>,
+    /// e.g. `https://original-domain.com`
+    #[serde(default)]
+    rewrite_origins: Vec<String>,
+    /// e.g. `blog.example.com`
+    canonical_host: Option<String>,
+    #[serde(default)]
+    reserved: bool,
+    #[serde(default)]
+    noindex: bool,
+    /// `full`, `anonymized` or `off`
+    #[serde(default)]
+    access_log: crate::access_log::AccessLog,
+    /// e.g. `["wss://relay.tenant.example"]`
+    #[serde(default)]
+    relays: Vec<String>,
+}
+
+impl TryFrom<AliasEntry> for SiteAlias {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: AliasEntry) -> Result<Self> {
+        let pubkey = PublicKey::parse(&entry.pubkey)
+            .map_err(|e| anyhow!("invalid pubkey '{}': {}", entry.pubkey, e))?;
+        let pin = match entry.pin {
+            Some(p) => Some(EventId::parse(&p).map_err(|e| anyhow!("invalid pin '{}': {}", p, e))?),
+            None => None,
+        };
+        let rewrite_origins = entry
+            .rewrite_origins
+            .iter()
+            .map(|o| parse_origin(o))
+            .collect::<Result<_>>()?;
+        if let Some(host) = &entry.canonical_host
+            && !is_valid_host(host)
+        {
+            return Err(anyhow!("invalid canonical host '{}'", host));
+        }
+        let relays = entry
+            .relays
+            .iter()
+            .map(|r| RelayUrl::parse(r).map_err(|e| anyhow!("invalid relay '{}': {}", r, e)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            pubkey: pubkey.to_bytes(),
+            identifier: entry.identifier,
+            pin,
+            rewrite_origins,
+            canonical_host: entry.canonical_host.map(|h| h.to_ascii_lowercase()),
+            reserved: entry.reserved,
+            noindex: entry.noindex,
+            access_log: entry.access_log,
+            relays,
+        })
+    }
+}
+
+/// Normalize an http(s) origin, rejecting URLs with a path
+fn parse_origin(origin: &str) -> Result<String> {
+    let url = Url::parse(origin).map_err(|e| anyhow!("invalid origin '{}': {}", origin, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() || url.path() != "/" {
+        return Err(anyhow!("'{}' is not an http(s) origin", origin));
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+/// Load the alias config file mapping subdomains to sites
+pub fn load_aliases(path: &Path) -> Result<HashMap<String, SiteAlias>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read alias file {}", path.display()))?;
+    parse_aliases(&json).with_context(|| format!("Invalid alias file {}", path.display()))
+}
+
+fn parse_aliases(json: &str) -> Result<HashMap<String, SiteAlias>> {
+    let entries: HashMap<String, AliasEntry> = serde_json::from_str(json)?;
+    let patterns = entries.keys().filter(|name| is_pattern(name)).count();
+    if patterns > MAX_ALIAS_PATTERNS {
+        return Err(anyhow!(
+            "{} wildcard aliases, at most {} are allowed",
+            patterns,
+            MAX_ALIAS_PATTERNS
+        ));
+    }
+    entries
+        .into_iter()
+        .map(|(name, entry)| {
+            if is_pattern(&name) {
+                if name.matches('*').count() > 1 || !is_valid_label(&name.replace('*', "x")) {
+                    return Err(anyhow!(
+                        "wildcard alias '{}' must be a label with a single '*'",
+                        name
+                    ));
+                }
+            } else if !is_valid_label(&name) {
+                return Err(anyhow!("alias '{}' is not a valid subdomain label", name));
+            } else if entry.identifier.as_ref().is_some_and(|id| id.contains('*')) {
+                return Err(anyhow!(
+                    "alias '{}' uses '*' in its identifier but is not a wildcard",
+                    name
+                ));
+            }
+            let alias = SiteAlias::try_from(entry).with_context(|| format!("alias '{}'", name))?;
+            Ok((name, alias))
+        })
+        .collect()
+}
+
+/// Maximum number of wildcard aliases, every one is tried when no alias matches exactly
+const MAX_ALIAS_PATTERNS: usize = 64;
+
+/// Whether an alias name is a wildcard pattern
+pub fn is_pattern(name: &str) -> bool {
+    name.contains('*')
+}
+
+/// Alias for a subdomain label, exact names win over wildcard patterns
+///
+/// The `*` of a pattern matches one or more characters and the pattern with the longest
+/// literal part wins, then the one with the longest part before the `*`, then the first
+/// name in order. A `*` in a pattern's identifier is replaced by the matched text, so
+/// `"docs-*": { "identifier": "*" }` serves `docs-v2` from the named site `v2`.
+pub fn lookup<'a>(
+    aliases: &'a HashMap<String, SiteAlias>,
+    label: &str,
+) -> Option<Cow<'a, SiteAlias>> {
+    if is_pattern(label) {
+        return None;
+    }
+    if let Some(alias) = aliases.get(label) {
+        return Some(Cow::Borrowed(alias));
+    }
+    let (captured, alias) = aliases
+        .iter()
+        .filter(|(name, _)| is_pattern(name))
+        .filter_map(|(name, alias)| {
+            let (prefix, suffix) = name.split_once('*')?;
+            let captured = label
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)
+                .filter(|c| !c.is_empty())?;
+            Some(((name.len(), prefix.len()), name, captured, alias))
+        })
+        .max_by(|(a, a_name, ..), (b, b_name, ..)| a.cmp(b).then_with(|| b_name.cmp(a_name)))
+        .map(|(_, _, captured, alias)| (captured, alias))?;
+    match &alias.identifier {
+        Some(id) if id.contains('*') => Some(Cow::Owned(SiteAlias {
+            identifier: Some(id.replace('*', captured)),
+            ..alias.clone()
+        })),
+        _ => Some(Cow::Borrowed(alias)),
+    }
+}
+
+/// Maximum number of entries in a short ID registry
+const MAX_REGISTRY_ENTRIES: usize = 10_000;
+
+/// Maximum length of a short ID, longer names belong in the alias file
+const MAX_REGISTRY_ID_LEN: usize = 8;
+
+/// Load a short ID registry mapping small numbers or slugs to root sites
+///
+/// ```json
+/// { "1": "npub1...", "42": "npub1...", "xy": "<hex pubkey>" }
+/// ```
+pub fn load_registry(path: &Path) -> Result<HashMap<String, SiteAlias>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read registry file {}", path.display()))?;
+    parse_registry(&json).with_context(|| format!("Invalid registry file {}", path.display()))
+}
+
+fn parse_registry(json: &str) -> Result<HashMap<String, SiteAlias>> {
+    let entries: HashMap<String, String> = serde_json::from_str(json)?;
+    if entries.len() > MAX_REGISTRY_ENTRIES {
+        return Err(anyhow!(
+            "registry has {} entries, at most {} are allowed",
+            entries.len(),
+            MAX_REGISTRY_ENTRIES
+        ));
+    }
+    entries
+        .into_iter()
+        .map(|(id, pubkey)| {
+            if id.len() > MAX_REGISTRY_ID_LEN || !is_valid_label(&id) {
+                return Err(anyhow!(
+                    "registry id '{}' is not a short subdomain label",
+                    id
+                ));
+            }
+            if id.len() > 1 && id.starts_with('0') && id.chars().all(|c| c.is_ascii_digit()) {
+                return Err(anyhow!("registry id '{}' has a leading zero", id));
+            }
+            let pubkey = PublicKey::parse(&pubkey)
+                .map_err(|e| anyhow!("registry id '{}': invalid pubkey '{}': {}", id, pubkey, e))?;
+            let alias = SiteAlias::new(pubkey.to_bytes());
+            Ok((id, alias))
+        })
+        .collect()
+}
+
+/// Check that a host, optionally with a port, can be put in a redirect
+fn is_valid_host(host: &str) -> bool {
+    Url::parse(&format!("http://{}/", host)).is_ok_and(|u| {
+        u.host_str()
+            .is_some_and(|h| h.eq_ignore_ascii_case(host.split(':').next().unwrap_or(host)))
+    })
+}
+
+/// Check that a name can be used as a single lowercase DNS label
+fn is_valid_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{Keys, ToBech32};
+
+    #[test]
+    fn test_parse_aliases() {
+        let keys = Keys::generate();
+        let pin = EventId::all_zeros();
+        let json = format!(
+            r#"{{
+                "blog": {{ "pubkey": "{}" }},
+                "docs-v1": {{ "pubkey": "{}", "identifier": "docs", "pin": "{}", "reserved": true, "noindex": true, "access_log": "off", "relays": ["wss://relay.tenant.example"] }}
+            }}"#,
+            keys.public_key().to_bech32().unwrap(),
+            keys.public_key().to_hex(),
+            pin.to_hex()
+        );
+        let aliases = parse_aliases(&json).unwrap();
+
+        let blog = &aliases["blog"];
+        assert_eq!(blog.pubkey, keys.public_key().to_bytes());
+        assert!(blog.identifier.is_none());
+        assert!(blog.pin.is_none());
+
+        assert!(!blog.reserved);
+        assert!(!blog.noindex);
+        assert!(blog.relays.is_empty());
+
+        let docs = &aliases["docs-v1"];
+        assert_eq!(docs.pubkey, keys.public_key().to_bytes());
+        assert_eq!(docs.identifier.as_deref(), Some("docs"));
+        assert_eq!(docs.pin, Some(pin));
+        assert!(docs.reserved);
+        assert!(docs.noindex);
+        assert_eq!(docs.access_log, crate::access_log::AccessLog::Off);
+        assert_eq!(
+            docs.relays,
+            vec![RelayUrl::parse("wss://relay.tenant.example").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_parse_aliases_invalid() {
+        let pubkey = Keys::generate().public_key().to_hex();
+
+        // Not a valid subdomain label
+        let json = format!(r#"{{ "Blog": {{ "pubkey": "{}" }} }}"#, pubkey);
+        assert!(parse_aliases(&json).is_err());
+
+        // Bad pin
+        let json = format!(
+            r#"{{ "blog": {{ "pubkey": "{}", "pin": "nope" }} }}"#,
+            pubkey
+        );
+        assert!(parse_aliases(&json).is_err());
+
+        // Bad pubkey
+        assert!(parse_aliases(r#"{ "blog": { "pubkey": "nope" } }"#).is_err());
+
+        // Rewrite origin with a path
+        let json = format!(
+            r#"{{ "blog": {{ "pubkey": "{}", "rewrite_origins": ["https://a.example/blog"] }} }}"#,
+            pubkey
+        );
+        assert!(parse_aliases(&json).is_err());
+
+        // Relay that isn't a websocket URL
+        let json = format!(
+            r#"{{ "blog": {{ "pubkey": "{}", "relays": ["https://a.example"] }} }}"#,
+            pubkey
+        );
+        assert!(parse_aliases(&json).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_aliases() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+        let json = format!(
+            r#"{{
+                "docs-*": {{ "pubkey": "{0}", "identifier": "*" }},
+                "docs-*-beta": {{ "pubkey": "{1}" }},
+                "docs-main": {{ "pubkey": "{1}", "identifier": "main" }}
+            }}"#,
+            keys.public_key().to_hex(),
+            other.public_key().to_hex()
+        );
+        let aliases = parse_aliases(&json).unwrap();
+
+        let v2 = lookup(&aliases, "docs-v2").unwrap();
+        assert_eq!(v2.pubkey, keys.public_key().to_bytes());
+        assert_eq!(v2.identifier.as_deref(), Some("v2"));
+
+        // Exact names and more specific patterns win
+        assert_eq!(
+            lookup(&aliases, "docs-main").unwrap().identifier.as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            lookup(&aliases, "docs-v3-beta").unwrap().pubkey,
+            other.public_key().to_bytes()
+        );
+
+        // Equally long patterns are picked by their prefix, whatever the map's order
+        for _ in 0..20 {
+            let json = format!(
+                r#"{{
+                    "*-docs": {{ "pubkey": "{0}" }},
+                    "docs-*": {{ "pubkey": "{1}" }}
+                }}"#,
+                keys.public_key().to_hex(),
+                other.public_key().to_hex()
+            );
+            let aliases = parse_aliases(&json).unwrap();
+            assert_eq!(
+                lookup(&aliases, "docs-x-docs").unwrap().pubkey,
+                other.public_key().to_bytes()
+            );
+        }
+
+        assert!(lookup(&aliases, "docs-").is_none());
+        assert!(lookup(&aliases, "docs-*").is_none());
+        assert!(lookup(&aliases, "blog").is_none());
+
+        let pubkey = keys.public_key().to_hex();
+        for name in ["a*b*", "*.docs", "Docs-*"] {
+            let json = format!(r#"{{ "{}": {{ "pubkey": "{}" }} }}"#, name, pubkey);
+            assert!(parse_aliases(&json).is_err(), "{}", name);
+        }
+        let json = format!(
+            r#"{{ "blog": {{ "pubkey": "{}", "identifier": "*" }} }}"#,
+            pubkey
+        );
+        assert!(parse_aliases(&json).is_err());
+
+        let many: Vec<String> = (0..=MAX_ALIAS_PATTERNS)
+            .map(|i| format!(r#""s{}-*": {{ "pubkey": "{}" }}"#, i, pubkey))
+            .collect();
+        assert!(parse_aliases(&format!("{{ {} }}", many.join(","))).is_err());
+    }
+
+    #[test]
+    fn test_parse_canonical_host() {
+        let pubkey = Keys::generate().public_key().to_hex();
+        let alias = |host: &str| {
+            parse_aliases(&format!(
+                r#"{{ "blog": {{ "pubkey": "{}", "canonical_host": "{}" }} }}"#,
+                pubkey, host
+            ))
+        };
+        let aliases = alias("Blog.example.com").unwrap();
+        assert_eq!(
+            aliases["blog"].canonical_host.as_deref(),
+            Some("blog.example.com")
+        );
+        assert!(alias("blog.example.com:8080").is_ok());
+        assert!(alias("blog.example.com/path").is_err());
+        assert!(alias("user@blog.example.com").is_err());
+    }
+
+    #[test]
+    fn test_parse_origin() {
+        assert_eq!(
+            parse_origin("https://a.example/").unwrap(),
+            "https://a.example"
+        );
+        assert_eq!(
+            parse_origin("http://A.example:8080").unwrap(),
+            "http://a.example:8080"
+        );
+        assert!(parse_origin("ftp://a.example").is_err());
+        assert!(parse_origin("a.example").is_err());
+    }
+
+    #[test]
+    fn test_parse_registry() {
+        let pubkey = Keys::generate().public_key();
+        let json = format!(
+            r#"{{ "42": "{}", "xy": "{}" }}"#,
+            pubkey.to_bech32().unwrap(),
+            pubkey.to_hex()
+        );
+        let registry = parse_registry(&json).unwrap();
+        assert_eq!(registry["42"].pubkey, pubkey.to_bytes());
+        assert!(registry["xy"].identifier.is_none());
+
+        let hex = pubkey.to_hex();
+        assert!(parse_registry(&format!(r#"{{ "042": "{}" }}"#, hex)).is_err());
+        assert!(parse_registry(&format!(r#"{{ "toolongid": "{}" }}"#, hex)).is_err());
+        assert!(parse_registry(r#"{ "1": "nope" }"#).is_err());
+
+        let too_many: HashMap<String, String> = (0..=MAX_REGISTRY_ENTRIES)
+            .map(|i| (i.to_string(), hex.clone()))
+            .collect();
+        assert!(parse_registry(&serde_json::to_string(&too_many).unwrap()).is_err());
+    }
+}
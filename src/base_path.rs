@@ -0,0 +1,121 @@
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Parse a `--base-path`, normalized to a leading slash and no trailing slash
+pub fn parse(s: &str) -> Result<String, String> {
+    let trimmed = s.trim_matches('/');
+    if trimmed.is_empty() {
+        return Err("base path can't be empty or '/'".to_string());
+    }
+    if trimmed.contains(['?', '#', ' '])
+        || trimmed
+            .split('/')
+            .any(|s| s.is_empty() || s == "." || s == "..")
+    {
+        return Err(format!("invalid base path '{}'", s));
+    }
+    Ok(format!("/{}", trimmed))
+}
+
+/// Path of a request below `base`, `None` when the request is outside it
+///
+/// `/sites` and `/sites/` both map to `/`, `/sitesfoo` isn't below `/sites`.
+pub fn strip<'a>(path: &'a str, base: &str) -> Option<&'a str> {
+    match path.strip_prefix(base)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Remove the base path from every request so sites resolve as if mounted at `/`
+///
+/// Requests outside the base path are answered with 404.
+pub async fn strip_base_path(
+    State(base): State<Option<String>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(base) = base else {
+        return next.run(request).await;
+    };
+    let Some(path) = strip(request.uri().path(), &base) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = match path_and_query.parse() {
+        Ok(p) => Some(p),
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    *request.uri_mut() = match Uri::from_parts(parts) {
+        Ok(uri) => uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("/sites/").unwrap(), "/sites");
+        assert_eq!(parse("sites").unwrap(), "/sites");
+        assert_eq!(parse("/a/b").unwrap(), "/a/b");
+        assert!(parse("/").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("/a/../b").is_err());
+        assert!(parse("/a//b").is_err());
+        assert!(parse("/a?b").is_err());
+    }
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(strip("/sites", "/sites"), Some("/"));
+        assert_eq!(strip("/sites/", "/sites"), Some("/"));
+        assert_eq!(strip("/sites/docs/a.html", "/sites"), Some("/docs/a.html"));
+        assert_eq!(strip("/sitesfoo", "/sites"), None);
+        assert_eq!(strip("/other", "/sites"), None);
+    }
+
+    #[tokio::test]
+    async fn test_strip_base_path() {
+        let echo = get(|request: Request| async move { request.uri().to_string() });
+        let app = Router::new()
+            .route("/", echo.clone())
+            .route("/{*path}", echo)
+            .layer(axum::middleware::from_fn_with_state(
+                Some("/sites".to_string()),
+                strip_base_path,
+            ));
+        let get = |uri: &str| {
+            let app = app.clone();
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        assert_eq!(
+            get("/sites/_nsite/ls?prefix=/docs/").await,
+            (StatusCode::OK, "/_nsite/ls?prefix=/docs/".to_string())
+        );
+        assert_eq!(get("/sites").await, (StatusCode::OK, "/".to_string()));
+        assert_eq!(get("/index.html").await.0, StatusCode::NOT_FOUND);
+    }
+}
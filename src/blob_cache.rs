@@ -0,0 +1,287 @@
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+#[cfg(feature = "redis")]
+use log::info;
+use log::warn;
+#[cfg(feature = "redis")]
+use nostr_sdk::Url;
+use std::path::Path;
+#[cfg(feature = "redis")]
+use std::time::Duration;
+
+/// Largest blob shared through Redis, bigger ones stay in the local disk cache only
+pub const MAX_SHARED_BLOB: u64 = 16 * 1024 * 1024;
+
+/// Time allowed for a single Redis command, a slow cache mustn't be slower than Blossom
+#[cfg(feature = "redis")]
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where downloaded blobs are kept, keyed by their sha256
+///
+/// Blobs are always served from a file on the local disk, `path` is where the blob of
+/// `key` goes. Backends sharing blobs between gateways copy them into place on `get`.
+pub trait BlobCache: Send + Sync {
+    /// Make the blob available at `path`, false if the cache doesn't have it
+    fn get<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>>;
+
+    /// Share a blob just downloaded to `path`
+    fn put<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<()>>;
+
+    /// Whether the cache has the blob, without copying it anywhere
+    fn contains<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>>;
+}
+
+/// Backend selected with `--cache-backend`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(clap::ValueEnum))]
+pub enum CacheBackend {
+    /// Blobs are only kept in the local cache directory
+    #[default]
+    Disk,
+    /// Blobs are shared through Redis, with the local cache directory in front
+    #[cfg(feature = "redis")]
+    Redis,
+}
+
+/// The local disk cache, blobs are downloaded straight into it
+pub struct DiskCache;
+
+impl DiskCache {
+    async fn has(key: &[u8; 32], path: &Path) -> bool {
+        // Empty files are left behind by an interrupted write or an external cleanup,
+        // unless the blob really is empty
+        tokio::fs::metadata(path)
+            .await
+            .is_ok_and(|m| m.len() > 0 || *key == crate::site::EMPTY_BLOB_HASH)
+    }
+}
+
+impl BlobCache for DiskCache {
+    fn get<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        async move {
+            if Self::has(key, path).await {
+                return Ok(true);
+            }
+            if tokio::fs::metadata(path).await.is_ok_and(|m| m.len() == 0) {
+                warn!("Cached file {} is empty, downloading again", path.display());
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            Ok(false)
+        }
+        .boxed()
+    }
+
+    fn put<'a>(&'a self, _key: &'a [u8; 32], _path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    fn contains<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        async move { Ok(Self::has(key, path).await) }.boxed()
+    }
+}
+
+/// Blobs shared between gateways through Redis, in front of which the local disk cache
+/// still serves every blob this gateway has seen
+#[cfg(feature = "redis")]
+pub struct RedisCache {
+    conn: redis::aio::ConnectionManager,
+    ttl: Option<Duration>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+    /// Use the Redis at a `redis://[user:password@]host[:port][/db]` URL, blobs expire after
+    /// `ttl`. The connection is opened on first use and reopened whenever it drops
+    pub fn new(url: &Url, ttl: Option<Duration>) -> Result<Self> {
+        let client = redis::Client::open(url.as_str())?;
+        let config = redis::aio::ConnectionManagerConfig::new()
+            .set_connection_timeout(Some(COMMAND_TIMEOUT))
+            .set_response_timeout(Some(COMMAND_TIMEOUT))
+            .set_number_of_retries(1);
+        let conn = redis::aio::ConnectionManager::new_lazy_with_config(client, config)?;
+        Ok(Self { conn, ttl })
+    }
+
+    fn redis_key(key: &[u8; 32]) -> String {
+        format!("nsite:blob:{}", hex::encode(key))
+    }
+
+    async fn fetch(&self, key: &[u8; 32], path: &Path) -> Result<bool> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.conn.clone();
+        let Some(bytes): Option<Vec<u8>> = conn.get(Self::redis_key(key)).await? else {
+            return Ok(false);
+        };
+        use sha2::Digest;
+        if sha2::Sha256::digest(&bytes).as_slice() != key {
+            warn!(
+                "Redis has content not matching {}, ignoring it",
+                hex::encode(key)
+            );
+            return Ok(false);
+        }
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        crate::site::write_atomic(path, &bytes).await?;
+        info!(
+            "Loaded {} from Redis, {} bytes",
+            hex::encode(key),
+            bytes.len()
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl BlobCache for RedisCache {
+    fn get<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        async move {
+            if DiskCache::has(key, path).await {
+                return Ok(true);
+            }
+            // A shared cache that's down only costs a download from Blossom
+            match self.fetch(key, path).await {
+                Ok(found) => Ok(found),
+                Err(e) => {
+                    warn!("Failed to load {} from Redis: {}", hex::encode(key), e);
+                    Ok(false)
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn put<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        async move {
+            use redis::AsyncCommands;
+
+            if tokio::fs::metadata(path).await?.len() > MAX_SHARED_BLOB {
+                return Ok(());
+            }
+            let bytes = tokio::fs::read(path).await?;
+            let mut conn = self.conn.clone();
+            match self.ttl {
+                Some(ttl) => {
+                    let secs = ttl.as_secs().max(1);
+                    conn.set_ex::<_, _, ()>(Self::redis_key(key), bytes, secs)
+                        .await?
+                }
+                None => conn.set::<_, _, ()>(Self::redis_key(key), bytes).await?,
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn contains<'a>(&'a self, key: &'a [u8; 32], path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        async move {
+            use redis::AsyncCommands;
+
+            if DiskCache::has(key, path).await {
+                return Ok(true);
+            }
+            let mut conn = self.conn.clone();
+            Ok(conn.exists(Self::redis_key(key)).await?)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disk_cache_empty_file() {
+        let key = [9u8; 32];
+        let path = std::env::temp_dir().join("nsite-disk-cache-empty-test.txt");
+        std::fs::write(&path, b"").unwrap();
+        assert!(!DiskCache.contains(&key, &path).await.unwrap());
+        assert!(path.exists());
+        // Read as a miss, and removed so it's downloaded again
+        assert!(!DiskCache.get(&key, &path).await.unwrap());
+        assert!(!path.exists());
+
+        std::fs::write(&path, b"cached").unwrap();
+        assert!(DiskCache.get(&key, &path).await.unwrap());
+        assert!(path.exists());
+        let _ = std::fs::remove_file(&path);
+
+        // The empty blob is kept
+        std::fs::write(&path, b"").unwrap();
+        assert!(
+            DiskCache
+                .get(&crate::site::EMPTY_BLOB_HASH, &path)
+                .await
+                .unwrap()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_redis_url() {
+        assert!(
+            RedisCache::new(&"redis://:secret@cache.internal/2".parse().unwrap(), None).is_ok()
+        );
+        assert!(RedisCache::new(&"http://cache.internal".parse().unwrap(), None).is_err());
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_unreachable_redis() {
+        use sha2::Digest;
+
+        let key: [u8; 32] = sha2::Sha256::digest(b"not shared").into();
+        let path = std::env::temp_dir().join("nsite-redis-down-test.txt");
+        let _ = std::fs::remove_file(&path);
+        // An unreachable Redis falls back to downloading
+        let down = RedisCache::new(&"redis://127.0.0.1:1".parse().unwrap(), None).unwrap();
+        assert!(!down.get(&key, &path).await.unwrap());
+        assert!(down.contains(&key, &path).await.is_err());
+    }
+
+    /// Needs a Redis server, run with `NSITE_TEST_REDIS_URL=redis://127.0.0.1:6379/15`
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "needs a Redis server at NSITE_TEST_REDIS_URL"]
+    async fn test_shared_blob() {
+        use redis::AsyncCommands;
+        use sha2::Digest;
+
+        let url: Url = std::env::var("NSITE_TEST_REDIS_URL")
+            .expect("NSITE_TEST_REDIS_URL")
+            .parse()
+            .unwrap();
+        let blob = b"shared between gateways";
+        let key: [u8; 32] = sha2::Sha256::digest(blob).into();
+        let other = [7u8; 32];
+        let dir = std::env::temp_dir().join("nsite-redis-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.txt"), dir.join("second").join("blob.txt"));
+        std::fs::write(&first, blob).unwrap();
+
+        let a = RedisCache::new(&url, None).unwrap();
+        let b = RedisCache::new(&url, Some(Duration::from_secs(60))).unwrap();
+        let mut conn = a.conn.clone();
+        let _: () = conn
+            .del(&[RedisCache::redis_key(&key), RedisCache::redis_key(&other)])
+            .await
+            .unwrap();
+
+        // One gateway downloaded the blob, another one picks it up from Redis
+        assert!(!b.contains(&key, &second).await.unwrap());
+        assert!(!b.get(&key, &second).await.unwrap());
+        a.put(&key, &first).await.unwrap();
+        assert!(b.contains(&key, &second).await.unwrap());
+        assert!(b.get(&key, &second).await.unwrap());
+        assert_eq!(std::fs::read(&second).unwrap(), blob);
+
+        // Content not matching its key is never served
+        a.put(&other, &first).await.unwrap();
+        assert!(!b.get(&other, &dir.join("other.txt")).await.unwrap());
+    }
+}
@@ -0,0 +1,111 @@
+/// How a downloaded blob is kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Storage {
+    /// Cached unless it's over the stream threshold
+    Default,
+    /// Always streamed to the client, never written to disk
+    Stream,
+    /// Always cached, whatever its size
+    Cache,
+}
+
+/// Content types whose blobs are always streamed or always cached
+///
+/// Patterns are a full type like `video/mp4` or a whole top-level type like `video/*`.
+/// The most specific pattern wins, a type matching both lists equally is cached.
+#[derive(Clone, Debug, Default)]
+pub struct ContentTypeRules {
+    pub stream: Vec<String>,
+    pub cache: Vec<String>,
+}
+
+impl ContentTypeRules {
+    pub fn is_empty(&self) -> bool {
+        self.stream.is_empty() && self.cache.is_empty()
+    }
+
+    /// How a blob of `content_type` is kept
+    pub fn storage(&self, content_type: &str) -> Storage {
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let best = |patterns: &[String]| {
+            patterns
+                .iter()
+                .filter_map(|p| specificity(p, &content_type))
+                .max()
+        };
+        match (best(&self.stream), best(&self.cache)) {
+            (Some(stream), Some(cache)) if stream > cache => Storage::Stream,
+            (_, Some(_)) => Storage::Cache,
+            (Some(_), None) => Storage::Stream,
+            (None, None) => Storage::Default,
+        }
+    }
+}
+
+/// Parse a content type pattern for `--stream-content-type` and `--cache-content-type`
+pub fn parse_pattern(s: &str) -> Result<String, String> {
+    let pattern = s.trim().to_ascii_lowercase();
+    match pattern.split_once('/') {
+        Some((top, sub))
+            if !top.is_empty() && top != "*" && !sub.is_empty() && !sub.contains('/') =>
+        {
+            Ok(pattern)
+        }
+        _ => Err(format!(
+            "invalid content type '{}', expected e.g. video/* or image/png",
+            s
+        )),
+    }
+}
+
+/// How closely `pattern` matches `content_type`, `None` if it doesn't
+fn specificity(pattern: &str, content_type: &str) -> Option<u8> {
+    match pattern.strip_suffix("/*") {
+        Some(top) => content_type
+            .split_once('/')
+            .is_some_and(|(t, _)| t == top)
+            .then_some(1),
+        None => (pattern == content_type).then_some(2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage() {
+        let rules = ContentTypeRules {
+            stream: vec![
+                "video/*".to_string(),
+                "audio/*".to_string(),
+                "image/gif".to_string(),
+            ],
+            cache: vec!["video/mp4".to_string(), "image/*".to_string()],
+        };
+        assert_eq!(rules.storage("video/webm"), Storage::Stream);
+        assert_eq!(rules.storage("Audio/Ogg; codecs=opus"), Storage::Stream);
+        assert_eq!(rules.storage("video/mp4"), Storage::Cache);
+        assert_eq!(rules.storage("image/png"), Storage::Cache);
+        assert_eq!(rules.storage("image/gif"), Storage::Stream);
+        assert_eq!(rules.storage("text/html"), Storage::Default);
+        assert_eq!(
+            ContentTypeRules::default().storage("video/mp4"),
+            Storage::Default
+        );
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(parse_pattern("Video/*").unwrap(), "video/*");
+        assert_eq!(parse_pattern("image/png").unwrap(), "image/png");
+        assert!(parse_pattern("video").is_err());
+        assert!(parse_pattern("*/*").is_err());
+        assert!(parse_pattern("video/").is_err());
+    }
+}
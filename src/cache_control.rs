@@ -0,0 +1,177 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+use nostr_sdk::Timestamp;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Seconds browsers and CDNs may keep each kind of file before asking again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxAges {
+    /// HTML can change whenever the site is republished, so it's always revalidated
+    pub html: u64,
+    /// Fingerprinted assets change name when their content changes
+    pub immutable: u64,
+    /// Everything else may be replaced under the same name, but rarely is
+    pub other: u64,
+}
+
+impl Default for MaxAges {
+    fn default() -> Self {
+        Self {
+            html: 60,
+            immutable: 31_536_000,
+            other: 3600,
+        }
+    }
+}
+
+/// Cache-Control header value for a site path
+pub fn policy(path: &str, max_ages: &MaxAges) -> String {
+    let path = Path::new(path);
+    let ext = path.extension().and_then(|e| e.to_str());
+    if path.as_os_str().to_string_lossy().ends_with('/')
+        || ext.is_none()
+        || matches!(ext, Some("html") | Some("htm"))
+    {
+        format!("public, max-age={}, must-revalidate", max_ages.html)
+    } else if path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .is_some_and(is_fingerprinted)
+    {
+        format!("public, max-age={}, immutable", max_ages.immutable)
+    } else {
+        format!("public, max-age={}", max_ages.other)
+    }
+}
+
+/// Cache-Control header value for a max-age a site declared itself, capped at `cap`
+pub fn site_policy(max_age: u64, cap: u64) -> String {
+    format!("public, max-age={}", max_age.min(cap))
+}
+
+/// `Last-Modified` header value for content published at `created_at`
+pub fn last_modified(created_at: Timestamp) -> HeaderValue {
+    let time = UNIX_EPOCH + Duration::from_secs(created_at.as_secs());
+    httpdate::fmt_http_date(time).parse().unwrap()
+}
+
+/// Whether the request's `If-Modified-Since` is at or after `created_at`
+///
+/// Only used without an `If-None-Match`, which takes precedence as RFC 9110 says.
+pub fn is_unmodified_since(headers: &HeaderMap, created_at: Timestamp) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|since| since.as_secs() >= created_at.as_secs())
+}
+
+/// Whether a file stem ends in a content hash, like `main.7f3a9c2e` or `index-DiwrgTda`
+fn is_fingerprinted(stem: &str) -> bool {
+    let Some(hash) = stem
+        .rsplit(['.', '-'])
+        .next()
+        .filter(|h| h.len() < stem.len())
+    else {
+        return false;
+    };
+    let has_digit = hash.chars().any(|c| c.is_ascii_digit());
+    let mixed_case = hash.chars().any(|c| c.is_ascii_uppercase())
+        && hash.chars().any(|c| c.is_ascii_lowercase());
+    (8..=64).contains(&hash.len())
+        && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && (has_digit || mixed_case)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let max_ages = MaxAges::default();
+        let html = "public, max-age=60, must-revalidate";
+        let immutable = "public, max-age=31536000, immutable";
+        let other = "public, max-age=3600";
+        assert_eq!(policy("/index.html", &max_ages), html);
+        assert_eq!(policy("/docs/", &max_ages), html);
+        assert_eq!(policy("/about", &max_ages), html);
+        assert_eq!(policy("/assets/index-DiwrgTda.js", &max_ages), immutable);
+        assert_eq!(
+            policy("/static/js/main.7f3a9c2e.chunk.js", &max_ages),
+            other
+        );
+        assert_eq!(policy("/static/js/main.7f3a9c2e.js", &max_ages), immutable);
+        assert_eq!(policy("/style.css", &max_ages), other);
+        assert_eq!(policy("/vendor/bootstrap.min.css", &max_ages), other);
+        assert_eq!(policy("/img/hero-sunset.jpg", &max_ages), other);
+
+        let max_ages = MaxAges {
+            html: 0,
+            immutable: 86400,
+            other: 600,
+        };
+        assert_eq!(
+            policy("/index.html", &max_ages),
+            "public, max-age=0, must-revalidate"
+        );
+        assert_eq!(
+            policy("/assets/index-DiwrgTda.js", &max_ages),
+            "public, max-age=86400, immutable"
+        );
+        assert_eq!(policy("/style.css", &max_ages), "public, max-age=600");
+    }
+
+    #[test]
+    fn test_site_policy() {
+        assert_eq!(site_policy(300, 86400), "public, max-age=300");
+        assert_eq!(site_policy(31_536_000, 86400), "public, max-age=86400");
+    }
+
+    #[test]
+    fn test_last_modified() {
+        let created_at = Timestamp::from(784111777);
+        assert_eq!(last_modified(created_at), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        let request = |headers: &[(header::HeaderName, &str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(name, value.parse().unwrap());
+            }
+            map
+        };
+        let since = |date: &str| request(&[(header::IF_MODIFIED_SINCE, date)]);
+        assert!(is_unmodified_since(
+            &since("Sun, 06 Nov 1994 08:49:37 GMT"),
+            created_at
+        ));
+        assert!(is_unmodified_since(
+            &since("Mon, 07 Nov 1994 08:49:37 GMT"),
+            created_at
+        ));
+        assert!(!is_unmodified_since(
+            &since("Sat, 05 Nov 1994 08:49:37 GMT"),
+            created_at
+        ));
+        assert!(!is_unmodified_since(&since("yesterday"), created_at));
+        assert!(!is_unmodified_since(&request(&[]), created_at));
+        let both = request(&[
+            (header::IF_MODIFIED_SINCE, "Mon, 07 Nov 1994 08:49:37 GMT"),
+            (header::IF_NONE_MATCH, "\"other\""),
+        ]);
+        assert!(!is_unmodified_since(&both, created_at));
+    }
+
+    #[test]
+    fn test_is_fingerprinted() {
+        assert!(is_fingerprinted("app.3f9a2b1c"));
+        assert!(is_fingerprinted("index-BdF3x9Qa"));
+        assert!(!is_fingerprinted("3f9a2b1c"));
+        assert!(!is_fingerprinted("jquery-3"));
+        assert!(!is_fingerprinted("my-component"));
+    }
+}
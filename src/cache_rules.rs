@@ -0,0 +1,78 @@
+use nostr_sdk::{Event, TagKind};
+use std::borrow::Cow;
+
+/// Most `cache` tags of a manifest looked at, so a site can't make every request scan
+/// thousands of patterns
+const MAX_CACHE_RULES: usize = 32;
+
+/// Max-age the first `cache` tag of a site manifest matching `path` declares
+///
+/// Rules are declared as `["cache", "<pattern>", "<seconds>"]` tags, where `*` in the
+/// pattern matches any run of characters, slashes included: `/images/*`, `*.html` or
+/// `/app.js`. Tags with an invalid max-age are skipped.
+pub fn max_age(manifest: &Event, path: &str) -> Option<u64> {
+    manifest
+        .tags
+        .filter(TagKind::Custom(Cow::Borrowed("cache")))
+        .take(MAX_CACHE_RULES)
+        .find_map(|tag| match tag.as_slice() {
+            [_, pattern, seconds, ..] if matches(pattern, path) => seconds.parse().ok(),
+            _ => None,
+        })
+}
+
+/// Whether `path` matches a pattern where `*` stands for any characters
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/app.js", "/app.js"));
+        assert!(!matches("/app.js", "/app.js.map"));
+        assert!(matches("/images/*", "/images/a/b.png"));
+        assert!(!matches("/images/*", "/img/a.png"));
+        assert!(matches("*.html", "/docs/index.html"));
+        assert!(!matches("*.html", "/docs/index.htm"));
+        assert!(matches("/assets/*.*.js", "/assets/main.3f9a.js"));
+        assert!(!matches("/a*a", "/a"));
+        assert!(matches("*", "/anything"));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let tags = [
+            &["cache", "/images/*", "31536000"][..],
+            &["cache", "*.html", "soon"],
+            &["cache", "*.html", "300"],
+            &["cache", "*", "60"],
+        ];
+        let manifest = EventBuilder::new(Kind::Custom(15128), "")
+            .tags(tags.iter().map(|t| Tag::parse(t.to_vec()).unwrap()))
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        assert_eq!(max_age(&manifest, "/images/logo.png"), Some(31_536_000));
+        assert_eq!(max_age(&manifest, "/index.html"), Some(300));
+        assert_eq!(max_age(&manifest, "/app.js"), Some(60));
+    }
+}
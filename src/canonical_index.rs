@@ -0,0 +1,52 @@
+/// Which URL of a directory index is canonical, the directory or its `index.html`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CanonicalIndex {
+    /// `/docs/`, requests for `/docs/index.html` are redirected to it
+    Slash,
+    /// `/docs/index.html`, requests for `/docs/` are redirected to it
+    IndexHtml,
+}
+
+/// Where a request for `path` should be redirected to reach the canonical URL of a
+/// directory index, `None` when `path` is canonical already
+///
+/// Only the literal request path is looked at, extensionless paths without a trailing
+/// slash are left alone as they may be files.
+pub fn redirect_target(path: &str, canonical: CanonicalIndex) -> Option<String> {
+    match canonical {
+        CanonicalIndex::Slash => path
+            .strip_suffix("index.html")
+            .filter(|p| p.ends_with('/'))
+            .map(str::to_string),
+        CanonicalIndex::IndexHtml => path.ends_with('/').then(|| format!("{}index.html", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_target() {
+        use CanonicalIndex::*;
+        assert_eq!(redirect_target("/index.html", Slash).as_deref(), Some("/"));
+        assert_eq!(
+            redirect_target("/docs/index.html", Slash).as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(redirect_target("/", Slash), None);
+        assert_eq!(redirect_target("/notindex.html", Slash), None);
+        assert_eq!(redirect_target("/docs", Slash), None);
+
+        assert_eq!(
+            redirect_target("/", IndexHtml).as_deref(),
+            Some("/index.html")
+        );
+        assert_eq!(
+            redirect_target("/docs/", IndexHtml).as_deref(),
+            Some("/docs/index.html")
+        );
+        assert_eq!(redirect_target("/index.html", IndexHtml), None);
+        assert_eq!(redirect_target("/docs", IndexHtml), None);
+    }
+}
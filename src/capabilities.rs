@@ -0,0 +1,83 @@
+use nostr_sdk::RelayUrl;
+use nsite::SiteConfig;
+use serde_json::{Map, Value, json};
+
+/// Where the capabilities document is served on the root domain
+pub const PATH: &str = ".well-known/nsite.json";
+
+/// Machine readable description of how this gateway serves sites, for `--capabilities`
+///
+/// Everything comes from the running configuration: `relays` are the ones currently in
+/// the pool, `path_routing` is set for `--gateway`, and `features` names each optional
+/// behaviour with whether it's enabled.
+pub fn document(
+    config: &SiteConfig,
+    relays: &[RelayUrl],
+    path_routing: bool,
+    features: &[(&str, bool)],
+) -> Value {
+    let features: Map<String, Value> = features
+        .iter()
+        .map(|(name, enabled)| (name.to_string(), Value::Bool(*enabled)))
+        .collect();
+    json!({
+        "software": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "routing": if path_routing { "path" } else { "subdomain" },
+        "site_names": {
+            "npub": true,
+            "named": true,
+            "hex": config.hex_subdomains,
+        },
+        "kinds": {
+            "root_site": config.root_site_kind.as_u16(),
+            "named_site": config.named_site_kind.as_u16(),
+            "server_list": config.server_list_kind.as_u16(),
+        },
+        "blossom": {
+            "default_servers": config.default_servers,
+            "fallback_servers": config.fallback_servers,
+            "discovery_servers": config.discovery_servers,
+        },
+        "relays": relays.iter().map(|r| r.as_str()).collect::<Vec<_>>(),
+        "features": features,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{Kind, Url};
+
+    #[test]
+    fn test_document() {
+        let config = SiteConfig {
+            default_servers: vec![Url::parse("https://blossom.example").unwrap()],
+            named_site_kind: Kind::Custom(35129),
+            hex_subdomains: true,
+            ..Default::default()
+        };
+        let relays = [RelayUrl::parse("wss://relay.example").unwrap()];
+        let doc = document(
+            &config,
+            &relays,
+            false,
+            &[("spa", true), ("compression", false)],
+        );
+        assert_eq!(doc["routing"], "subdomain");
+        assert_eq!(doc["site_names"]["hex"], true);
+        assert_eq!(doc["kinds"]["named_site"], 35129);
+        assert_eq!(
+            doc["blossom"]["default_servers"],
+            json!(["https://blossom.example/"])
+        );
+        assert_eq!(doc["blossom"]["fallback_servers"], json!([]));
+        assert_eq!(doc["blossom"]["discovery_servers"], json!([]));
+        assert_eq!(doc["relays"], json!(["wss://relay.example"]));
+        assert_eq!(
+            doc["features"],
+            json!({ "spa": true, "compression": false })
+        );
+        assert_eq!(doc["version"], env!("CARGO_PKG_VERSION"));
+    }
+}
@@ -0,0 +1,177 @@
+use axum::http::{Extensions, HeaderMap, StatusCode, Version, header};
+use axum::response::Response;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+
+/// Marks a response the gateway generated itself, like the `/_nsite/` JSON endpoints or a
+/// generated sitemap, which are compressed even without `--compress`
+#[derive(Clone, Copy)]
+pub struct Generated;
+
+/// Mark a response as generated by the gateway
+pub fn generated(mut response: Response) -> Response {
+    response.extensions_mut().insert(Generated);
+    response
+}
+
+/// Content encodings responses can be compressed with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    Br,
+    Gzip,
+}
+
+/// Content types that are compressed already, besides raster images, so compressing them
+/// again only costs CPU
+const PRECOMPRESSED: [&str; 8] = [
+    "font/woff",
+    "video/",
+    "audio/",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+];
+
+/// Response compression negotiated from the request's `Accept-Encoding`
+///
+/// Responses smaller than `min_bytes` are always served uncompressed since compressing
+/// them wastes CPU and can even make them larger, as are images other than SVG and the
+/// [`PRECOMPRESSED`] types. Only `encodings` are offered. When `enabled` is false only
+/// [`Generated`] responses are compressed.
+pub fn compression_layer(
+    enabled: bool,
+    min_bytes: u16,
+    encodings: &[Encoding],
+) -> CompressionLayer<impl Predicate + use<>> {
+    let predicate = SizeAbove::new(min_bytes)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(
+            |_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+                !headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|t| PRECOMPRESSED.iter().any(|p| t.starts_with(p)))
+            },
+        )
+        .and(
+            move |_: StatusCode, _: Version, _: &HeaderMap, extensions: &Extensions| {
+                enabled || extensions.get::<Generated>().is_some()
+            },
+        );
+    CompressionLayer::new()
+        .br(encodings.contains(&Encoding::Br))
+        .gzip(encodings.contains(&Encoding::Gzip))
+        .compress_when(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::{Request, header};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    async fn content_encoding(app: Router, path: &str) -> Option<String> {
+        accepted_encoding(app, path, "gzip").await
+    }
+
+    async fn accepted_encoding(app: Router, path: &str, accept: &str) -> Option<String> {
+        let request = Request::get(path)
+            .header(header::ACCEPT_ENCODING, accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    fn app(enabled: bool) -> Router {
+        app_with(enabled, &[Encoding::Br, Encoding::Gzip])
+    }
+
+    fn app_with(enabled: bool, encodings: &[Encoding]) -> Router {
+        Router::new()
+            .route("/small", get(|| async { "a".repeat(200) }))
+            .route("/large", get(|| async { "a".repeat(4096) }))
+            .route(
+                "/generated",
+                get(|| async { generated("a".repeat(4096).into_response()) }),
+            )
+            .route(
+                "/font.woff2",
+                get(|| async { ([(header::CONTENT_TYPE, "font/woff2")], "a".repeat(4096)) }),
+            )
+            .route(
+                "/app.js",
+                get(|| async {
+                    (
+                        [(header::CONTENT_TYPE, "text/javascript")],
+                        "a".repeat(4096),
+                    )
+                }),
+            )
+            .layer(compression_layer(enabled, 1024, encodings))
+    }
+
+    #[tokio::test]
+    async fn test_small_response_uncompressed() {
+        assert_eq!(content_encoding(app(true), "/small").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_large_response_compressed() {
+        assert_eq!(
+            content_encoding(app(true), "/large").await.as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(content_encoding(app(false), "/large").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_generated_response_always_compressed() {
+        for enabled in [true, false] {
+            assert_eq!(
+                content_encoding(app(enabled), "/generated")
+                    .await
+                    .as_deref(),
+                Some("gzip")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_uncompressed() {
+        assert_eq!(content_encoding(app(true), "/font.woff2").await, None);
+        assert_eq!(
+            content_encoding(app(true), "/app.js").await.as_deref(),
+            Some("gzip")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enabled_encodings() {
+        assert_eq!(
+            accepted_encoding(app(true), "/app.js", "gzip, br")
+                .await
+                .as_deref(),
+            Some("br")
+        );
+        let gzip_only = app_with(true, &[Encoding::Gzip]);
+        assert_eq!(
+            accepted_encoding(gzip_only.clone(), "/app.js", "gzip, br")
+                .await
+                .as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(accepted_encoding(gzip_only, "/app.js", "br").await, None);
+    }
+}
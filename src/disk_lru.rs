@@ -0,0 +1,232 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Size and recency of the blob files in the disk cache, to keep it under a byte cap
+///
+/// Blob files are recorded as they're served or written. Once the total goes over the cap
+/// the least recently used files are deleted, whole, until it fits again. Recency isn't
+/// kept across restarts: the directory is scanned on startup and files are ranked by
+/// modification time.
+pub struct DiskLru {
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Size and last use of each file, higher ticks are more recent
+    files: HashMap<PathBuf, (u64, u64)>,
+    total: u64,
+    tick: u64,
+}
+
+impl DiskLru {
+    /// Empty tracker for a cache of at most `max_bytes`
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Tracker for the blobs already in `dir`, evicting right away if they're over the cap
+    pub fn scan(dir: &Path, max_bytes: u64) -> Result<Self> {
+        let mut found = Vec::new();
+        for shard in std::fs::read_dir(dir)? {
+            let shard = shard?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(&shard)? {
+                let path = file?.path();
+                if !is_blob_file(&path) {
+                    continue;
+                }
+                let meta = std::fs::metadata(&path)?;
+                found.push((meta.modified()?, path, meta.len()));
+            }
+        }
+        found.sort();
+        let lru = Self::new(max_bytes);
+        let mut evicted = Vec::new();
+        for (_, path, size) in found {
+            evicted.extend(lru.record(path, size));
+        }
+        for path in &evicted {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!(
+                    "Failed to evict {} from the disk cache, {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+        info!(
+            "Disk cache holds {} bytes of blobs, evicted {} files to stay within {} bytes",
+            lru.total(),
+            evicted.len(),
+            max_bytes
+        );
+        Ok(lru)
+    }
+
+    /// Bytes of blob files currently tracked
+    pub fn total(&self) -> u64 {
+        self.state.lock().unwrap().total
+    }
+
+    /// Mark a cache file as just used, picking it up if it was written since the last look
+    ///
+    /// Newly seen files count towards the cap and may push older files out of the cache,
+    /// which are deleted here.
+    pub async fn used(&self, path: &Path) {
+        if self.touch(path) {
+            return;
+        }
+        let size = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => return,
+        };
+        let evicted = self.record(path.to_path_buf(), size);
+        for victim in &evicted {
+            if let Err(e) = tokio::fs::remove_file(victim).await
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!(
+                    "Failed to evict {} from the disk cache, {}",
+                    victim.display(),
+                    e
+                );
+            }
+        }
+        if !evicted.is_empty() {
+            log::debug!("Evicted {} files from the disk cache", evicted.len());
+            crate::metrics::METRICS.record_disk_evictions(evicted.len() as u64);
+        }
+        crate::metrics::METRICS.set_disk_cache_bytes(self.total());
+    }
+
+    /// Bump a known file to most recently used, false when it isn't tracked
+    fn touch(&self, path: &Path) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        match state.files.get_mut(path) {
+            Some(entry) => {
+                entry.1 = tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Track a file as most recently used and hand back the files to delete to fit the cap
+    ///
+    /// The file just recorded is never evicted, even when it's over the cap on its own.
+    fn record(&self, path: PathBuf, size: u64) -> Vec<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        if let Some((old, _)) = state.files.insert(path.clone(), (size, tick)) {
+            state.total -= old;
+        }
+        state.total += size;
+        if state.total <= self.max_bytes {
+            return Vec::new();
+        }
+        let mut oldest: Vec<_> = state
+            .files
+            .iter()
+            .filter(|(p, _)| **p != path)
+            .map(|(p, (size, tick))| (*tick, *size, p.clone()))
+            .collect();
+        oldest.sort();
+        let mut evicted = Vec::new();
+        for (_, size, victim) in oldest {
+            if state.total <= self.max_bytes {
+                break;
+            }
+            state.files.remove(&victim);
+            state.total -= size;
+            evicted.push(victim);
+        }
+        evicted
+    }
+}
+
+/// Whether a file in a cache shard is a blob, named after its hex hash, rather than a
+/// partial download (`<hash>.<ext>.part<n>`) or anything else
+pub fn is_blob_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    let (hash, exts) = name.split_once('.').unwrap_or((name, ""));
+    hash.len() == 64
+        && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        && !exts.split('.').any(|e| {
+            e.strip_prefix("part")
+                .is_some_and(|n| n.parse::<u64>().is_ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_evicts_least_recently_used() {
+        let lru = DiskLru::new(100);
+        let (a, b, c) = (PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c"));
+        assert!(lru.record(a.clone(), 40).is_empty());
+        assert!(lru.record(b.clone(), 40).is_empty());
+        assert!(lru.touch(&a));
+        assert_eq!(lru.record(c.clone(), 40), vec![b.clone()]);
+        assert_eq!(lru.total(), 80);
+
+        // Rewriting a file replaces its size, and a file alone over the cap stays
+        assert!(lru.record(c.clone(), 10).is_empty());
+        assert_eq!(lru.total(), 50);
+        assert_eq!(lru.record(b.clone(), 200), vec![a, c]);
+        assert_eq!(lru.total(), 200);
+        assert!(!lru.touch(Path::new("d")));
+    }
+
+    #[tokio::test]
+    async fn test_scan_and_evict() {
+        let dir = std::env::temp_dir().join(format!("nsite-lru-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("aa")).unwrap();
+        let blob = |i: u8| {
+            dir.join("aa")
+                .join(format!("{}.html", hex::encode([i; 32])))
+        };
+        for i in 0..3 {
+            std::fs::write(blob(i), [0u8; 10]).unwrap();
+            let file = std::fs::File::options().write(true).open(blob(i)).unwrap();
+            let mtime =
+                std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000 + i as u64);
+            file.set_modified(mtime).unwrap();
+        }
+        std::fs::write(
+            dir.join("aa")
+                .join(format!("{}.html.part0", hex::encode([9u8; 32]))),
+            [0u8; 50],
+        )
+        .unwrap();
+
+        // The oldest file goes at startup already
+        let lru = DiskLru::scan(&dir, 25).unwrap();
+        assert_eq!(lru.total(), 20);
+        assert!(!blob(0).exists());
+
+        std::fs::write(blob(3), [0u8; 10]).unwrap();
+        lru.used(&blob(1)).await;
+        lru.used(&blob(3)).await;
+        assert_eq!(lru.total(), 20);
+        assert!(blob(1).exists() && !blob(2).exists() && blob(3).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
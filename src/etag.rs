@@ -0,0 +1,130 @@
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+
+/// Strong ETag of a blob, or of a `variant` of it such as a resized image
+pub fn etag(hash: &[u8; 32], variant: Option<&str>) -> HeaderValue {
+    let tag = match variant {
+        Some(variant) => format!("\"{}-{}\"", hex::encode(hash), variant),
+        None => format!("\"{}\"", hex::encode(hash)),
+    };
+    tag.parse().unwrap()
+}
+
+/// Strong ETag of a response body the gateway generated, like rewritten HTML
+pub fn body_etag(body: &[u8]) -> HeaderValue {
+    etag(&Sha256::digest(body).into(), None)
+}
+
+/// Mark the `ETag` of every response weak, for `--compress`
+///
+/// Compressed and uncompressed encodings of a blob share its tag, which RFC 9110 only
+/// allows for weak validators.
+pub async fn weaken(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let weak = response
+        .headers()
+        .get(header::ETAG)
+        .filter(|tag| !tag.as_bytes().starts_with(b"W/"))
+        .and_then(|tag| HeaderValue::from_bytes(&[b"W/", tag.as_bytes()].concat()).ok());
+    if let Some(weak) = weak {
+        response.headers_mut().insert(header::ETAG, weak);
+    }
+    response
+}
+
+/// Whether the request's `If-None-Match` lists `etag`, so the client's copy is current
+///
+/// Tags are compared weakly, as RFC 9110 asks for `If-None-Match`.
+pub fn is_fresh(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag() {
+        let hash = [0xabu8; 32];
+        assert_eq!(
+            etag(&hash, None),
+            format!("\"{}\"", "ab".repeat(32)).as_str()
+        );
+        assert_eq!(
+            etag(&hash, Some("w320")),
+            format!("\"{}-w320\"", "ab".repeat(32)).as_str()
+        );
+        assert_ne!(body_etag(b"a"), body_etag(b"b"));
+    }
+
+    #[tokio::test]
+    async fn test_weaken() {
+        use axum::Router;
+        use axum::body::Body;
+        use axum::routing::get;
+        use tower::ServiceExt;
+
+        let tag = etag(&[2u8; 32], None);
+        let strong = tag.clone();
+        let app = Router::new()
+            .route(
+                "/",
+                get(move || async move { ([(header::ETAG, strong)], "blob") }),
+            )
+            .route(
+                "/weak",
+                get(|| async { ([(header::ETAG, "W/\"b\"")], "blob") }),
+            )
+            .layer(axum::middleware::from_fn(weaken));
+        let etag_of = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(path).body(Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                response.headers()[header::ETAG]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+        assert_eq!(etag_of("/").await, format!("W/{}", tag.to_str().unwrap()));
+        assert_eq!(etag_of("/weak").await, "W/\"b\"");
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let tag = etag(&[1u8; 32], None);
+        let request = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for v in values {
+                headers.append(header::IF_NONE_MATCH, v.parse().unwrap());
+            }
+            headers
+        };
+        assert!(!is_fresh(&request(&[]), &tag));
+        assert!(is_fresh(&request(&[tag.to_str().unwrap()]), &tag));
+        assert!(is_fresh(
+            &request(&[&format!("\"other\", W/{}", tag.to_str().unwrap())]),
+            &tag
+        ));
+        assert!(is_fresh(
+            &request(&["\"other\"", tag.to_str().unwrap()]),
+            &tag
+        ));
+        assert!(is_fresh(&request(&["*"]), &tag));
+        assert!(!is_fresh(&request(&["\"other\""]), &tag));
+    }
+}
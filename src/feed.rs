@@ -0,0 +1,185 @@
+use nostr_sdk::{Event, EventId, TagKind, Timestamp};
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Most updates kept in the feed of a site
+const MAX_ITEMS: usize = 20;
+
+/// Most sites with a feed, the least recently updated one is dropped beyond this
+const MAX_SITES: usize = 1024;
+
+/// Most changed paths listed in a single update
+const MAX_CHANGED: usize = 50;
+
+/// Manifest versions the gateway saw for each site, by site cache key
+static FEEDS: once_cell::sync::Lazy<Mutex<HashMap<String, SiteFeed>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct SiteFeed {
+    /// Path tags of the newest manifest, path => sha256
+    paths: HashMap<String, String>,
+    /// Newest first
+    items: VecDeque<Update>,
+}
+
+/// A manifest version of a site as seen by the gateway
+#[derive(Clone, Debug)]
+struct Update {
+    id: EventId,
+    created_at: Timestamp,
+    /// Paths added, removed or changed since the previous version, `None` for the first
+    /// version the gateway saw
+    changed: Option<Vec<String>>,
+}
+
+/// Record a manifest the gateway loaded for the site `site_key`
+///
+/// Seeing the same manifest again, or one older than the newest recorded, changes nothing.
+pub fn observe(site_key: &str, manifest: &Event) {
+    let mut feeds = FEEDS.lock().unwrap();
+    if !feeds.contains_key(site_key) && feeds.len() >= MAX_SITES {
+        let oldest = feeds
+            .iter()
+            .min_by_key(|(_, f)| f.items.front().map(|u| u.created_at))
+            .map(|(k, _)| k.clone());
+        if let Some(oldest) = oldest {
+            feeds.remove(&oldest);
+        }
+    }
+    let feed = feeds.entry(site_key.to_string()).or_default();
+    if feed
+        .items
+        .front()
+        .is_some_and(|u| u.id == manifest.id || u.created_at > manifest.created_at)
+    {
+        return;
+    }
+    let paths = path_tags(manifest);
+    let changed = (!feed.items.is_empty()).then(|| changed_paths(&feed.paths, &paths));
+    feed.paths = paths;
+    feed.items.push_front(Update {
+        id: manifest.id,
+        created_at: manifest.created_at,
+        changed,
+    });
+    feed.items.truncate(MAX_ITEMS);
+}
+
+/// JSON Feed of the updates recorded for the site `site_key`
+pub fn json_feed(site_key: &str, home_page_url: &str, feed_url: &str) -> Value {
+    let feeds = FEEDS.lock().unwrap();
+    let items: Vec<Value> = feeds
+        .get(site_key)
+        .map(|f| f.items.iter().map(|u| item(u, home_page_url)).collect())
+        .unwrap_or_default();
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": format!("Updates of {}", home_page_url),
+        "home_page_url": home_page_url,
+        "feed_url": feed_url,
+        "items": items,
+    })
+}
+
+fn item(update: &Update, home_page_url: &str) -> Value {
+    let (title, content) = match &update.changed {
+        None => (
+            "First seen".to_string(),
+            "First version seen by this gateway".to_string(),
+        ),
+        Some(changed) => (
+            format!("{} paths changed", changed.len()),
+            if changed.len() > MAX_CHANGED {
+                format!("{}, ...", changed[..MAX_CHANGED].join(", "))
+            } else {
+                changed.join(", ")
+            },
+        ),
+    };
+    json!({
+        "id": update.id.to_hex(),
+        "url": home_page_url,
+        "title": title,
+        "content_text": content,
+        "date_published": update.created_at.to_human_datetime(),
+    })
+}
+
+fn path_tags(manifest: &Event) -> HashMap<String, String> {
+    manifest
+        .tags
+        .filter(TagKind::Custom(Cow::Borrowed("path")))
+        .filter_map(|t| match t.as_slice() {
+            [_, path, hash, ..] => Some((path.clone(), hash.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Paths added, removed or pointing at a different blob, sorted
+fn changed_paths(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Vec<String> {
+    let mut changed: Vec<String> = new
+        .iter()
+        .filter(|(path, hash)| old.get(*path) != Some(*hash))
+        .map(|(path, _)| path.clone())
+        .chain(old.keys().filter(|p| !new.contains_key(*p)).cloned())
+        .collect();
+    changed.sort_unstable();
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    fn manifest(keys: &Keys, created_at: u64, paths: &[(&str, u8)]) -> Event {
+        EventBuilder::new(Kind::Custom(15128), "")
+            .tags(
+                paths
+                    .iter()
+                    .map(|(p, b)| Tag::parse(["path", p, &hex::encode([*b; 32])]).unwrap()),
+            )
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_feed() {
+        let keys = Keys::generate();
+        let key = keys.public_key().to_hex();
+        let first = manifest(&keys, 100, &[("/index.html", 1), ("/old.html", 2)]);
+        let second = manifest(&keys, 200, &[("/index.html", 3), ("/new.html", 4)]);
+        observe(&key, &first);
+        observe(&key, &first);
+        observe(&key, &second);
+        // An older manifest served by a lagging relay isn't an update
+        observe(&key, &manifest(&keys, 150, &[("/index.html", 5)]));
+
+        let feed = json_feed(
+            &key,
+            "https://blog.example.com",
+            "https://blog.example.com/_nsite/feed.json",
+        );
+        let items = feed["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["id"], second.id.to_hex());
+        assert_eq!(
+            items[0]["content_text"],
+            "/index.html, /new.html, /old.html"
+        );
+        assert_eq!(items[1]["title"], "First seen");
+
+        // Other sites' updates don't show up
+        let other = json_feed(
+            "unknown",
+            "https://a.example.com",
+            "https://a.example.com/_nsite/feed.json",
+        );
+        assert!(other["items"].as_array().unwrap().is_empty());
+    }
+}
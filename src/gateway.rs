@@ -0,0 +1,143 @@
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, Uri, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// First path segments the gateway answers itself rather than taking for a site
+const RESERVED: [&str; 2] = ["metrics", "_nsite"];
+
+/// Parent domain sites named in the path are resolved under, so `/npub1.../` resolves
+/// exactly like `npub1....<domain>` would
+const SITE_DOMAIN: &str = "path.invalid";
+
+/// Host resolving to no site at all, for requests to the gateway itself in path mode
+pub const ROOT_HOST: &str = SITE_DOMAIN;
+
+/// Site a path mode request named in its first path segment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathSite(pub String);
+
+impl PathSite {
+    /// Host to resolve the site from, as if it had been requested on its subdomain
+    pub fn host(&self) -> String {
+        format!("{}.{}", self.0, SITE_DOMAIN)
+    }
+}
+
+/// Split `/<site>/<path>` into the site segment and the path below it, which is empty
+/// when the site was named without a trailing slash
+///
+/// Segments with a dot, like `/favicon.ico` or `/.well-known`, and the [`RESERVED`] ones
+/// stay with the gateway.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix('/')?;
+    let (segment, below) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    if segment.is_empty() || segment.contains(['.', '%']) || RESERVED.contains(&segment) {
+        return None;
+    }
+    Some((segment, below))
+}
+
+/// Resolve sites from the first path segment instead of the host, for `--gateway`
+///
+/// The segment is removed from the request and handed on as a [`PathSite`]. A site named
+/// without a trailing slash is redirected to it, so the relative links of its pages
+/// resolve below the site rather than next to it.
+pub async fn route_by_path(
+    State(base): State<Option<String>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some((segment, below)) = split(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let site = PathSite(segment.to_string());
+    let query = request
+        .uri()
+        .query()
+        .map(|q| format!("?{}", q))
+        .unwrap_or_default();
+    if below.is_empty() {
+        let location = format!("{}/{}/{}", base.unwrap_or_default(), site.0, query);
+        return (
+            StatusCode::PERMANENT_REDIRECT,
+            [(header::LOCATION, location)],
+        )
+            .into_response();
+    }
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = match format!("{}{}", below, query).parse() {
+        Ok(p) => Some(p),
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    *request.uri_mut() = match Uri::from_parts(parts) {
+        Ok(uri) => uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    request.extensions_mut().insert(site);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("/npub1abc/"), Some(("npub1abc", "/")));
+        assert_eq!(
+            split("/npub1abc/css/a.css"),
+            Some(("npub1abc", "/css/a.css"))
+        );
+        assert_eq!(split("/npub1abc"), Some(("npub1abc", "")));
+        for root in [
+            "/",
+            "/favicon.ico",
+            "/.well-known/nostr.json",
+            "/metrics",
+            "/_nsite/tls-ask",
+            "/a%2Eb/",
+        ] {
+            assert_eq!(split(root), None, "{}", root);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_by_path() {
+        let app = Router::new()
+            .fallback(get(|request: Request| async move {
+                let site = request.extensions().get::<PathSite>().map(|s| s.0.clone());
+                format!("{:?} {}", site, request.uri())
+            }))
+            .layer(axum::middleware::from_fn_with_state(
+                Some("/g".to_string()),
+                route_by_path,
+            ));
+        let get = |uri: &str| {
+            let app = app.clone();
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            async move { app.oneshot(request).await.unwrap() }
+        };
+        let body = |response: Response| async {
+            String::from_utf8(
+                axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap()
+                    .to_vec(),
+            )
+            .unwrap()
+        };
+
+        let response = get("/npub1abc/a.css?v=1").await;
+        assert_eq!(body(response).await, "Some(\"npub1abc\") /a.css?v=1");
+        assert_eq!(body(get("/metrics").await).await, "None /metrics");
+
+        let response = get("/npub1abc?v=1").await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/g/npub1abc/?v=1");
+    }
+}
@@ -0,0 +1,101 @@
+use nostr_sdk::{Event, TagKind};
+use std::borrow::Cow;
+
+/// What to do with a request from a matching country
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeoRule {
+    /// Refuse the request with 451 Unavailable For Legal Reasons
+    Block,
+    /// Serve this path instead of `/index.html`
+    Index(String),
+    /// Redirect to this path or URL
+    Redirect(String),
+}
+
+/// First geo rule of a site manifest matching `country`
+///
+/// Rules are declared as `["geo", "<CC>[,<CC>...]|*", "block"|"index"|"redirect", "<target>"]`
+/// tags, `country` is the ISO 3166 code injected by the CDN.
+pub fn geo_rule(manifest: &Event, country: &str) -> Option<GeoRule> {
+    manifest
+        .tags
+        .filter(TagKind::Custom(Cow::Borrowed("geo")))
+        .find_map(|tag| {
+            let (countries, action) = match tag.as_slice() {
+                [_, countries, action, ..] => (countries, action),
+                _ => return None,
+            };
+            let matches = countries
+                .split(',')
+                .any(|c| c.trim() == "*" || c.trim().eq_ignore_ascii_case(country));
+            if !matches {
+                return None;
+            }
+            let target = tag.as_slice().get(3);
+            match (action.as_str(), target) {
+                ("block", _) => Some(GeoRule::Block),
+                ("index", Some(path)) if path.starts_with('/') => {
+                    Some(GeoRule::Index(path.clone()))
+                }
+                ("redirect", Some(target)) if is_redirect_target(target) => {
+                    Some(GeoRule::Redirect(target.clone()))
+                }
+                _ => None,
+            }
+        })
+}
+
+/// Redirects must stay on the site or go to an http(s) URL
+fn is_redirect_target(target: &str) -> bool {
+    (target.starts_with('/') && !target.starts_with("//"))
+        || target.starts_with("https://")
+        || target.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    fn manifest(rules: &[&[&str]]) -> Event {
+        let tags = rules.iter().map(|r| Tag::parse(r.to_vec()).unwrap());
+        EventBuilder::new(Kind::Custom(15128), "")
+            .tags(tags)
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_geo_rule() {
+        let manifest = manifest(&[
+            &["geo", "KP", "block"],
+            &["geo", "DE,AT", "index", "/index.de.html"],
+            &["geo", "FR", "redirect", "https://fr.example.com/"],
+            &["geo", "*", "index", "/index.en.html"],
+        ]);
+        assert_eq!(geo_rule(&manifest, "kp"), Some(GeoRule::Block));
+        assert_eq!(
+            geo_rule(&manifest, "AT"),
+            Some(GeoRule::Index("/index.de.html".into()))
+        );
+        assert_eq!(
+            geo_rule(&manifest, "FR"),
+            Some(GeoRule::Redirect("https://fr.example.com/".into()))
+        );
+        assert_eq!(
+            geo_rule(&manifest, "US"),
+            Some(GeoRule::Index("/index.en.html".into()))
+        );
+    }
+
+    #[test]
+    fn test_geo_rule_invalid() {
+        let manifest = manifest(&[
+            &["geo", "DE", "redirect", "//evil.example"],
+            &["geo", "DE", "index", "index.de.html"],
+            &["geo", "DE", "unknown"],
+        ]);
+        assert_eq!(geo_rule(&manifest, "DE"), None);
+        assert_eq!(geo_rule(&manifest, "US"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use http::{HeaderMap, HeaderName, HeaderValue};
+use log::warn;
+use nostr_sdk::{Event, TagKind};
+use std::borrow::Cow;
+
+/// Maximum number of headers a site may declare
+const MAX_SITE_HEADERS: usize = 32;
+
+/// Maximum combined size of all site header names and values
+const MAX_SITE_HEADERS_BYTES: usize = 8 * 1024;
+
+/// Headers a site must never set
+///
+/// Hop-by-hop and framing headers would break the proxy, the rest are owned by the
+/// gateway: CORS and cookies are shared by every site, and HSTS would apply to the
+/// gateway domain itself.
+const DENIED_HEADERS: [&str; 17] = [
+    "host",
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+    "content-encoding",
+    "content-type",
+    "content-range",
+    "set-cookie",
+    "strict-transport-security",
+    "alt-svc",
+];
+
+fn is_denied(name: &HeaderName) -> bool {
+    DENIED_HEADERS.contains(&name.as_str()) || name.as_str().starts_with("access-control-")
+}
+
+/// Parse the `["header", "<name>", "<value>"]` tags of a site manifest
+///
+/// Invalid or denied headers are skipped, and parsing stops once the count or size
+/// limit is reached so a site can't inflate every response it serves.
+pub fn site_headers(manifest: &Event) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut total_bytes = 0;
+    for tag in manifest
+        .tags
+        .filter(TagKind::Custom(Cow::Borrowed("header")))
+    {
+        let [_, name, value, ..] = tag.as_slice() else {
+            continue;
+        };
+        if headers.len() >= MAX_SITE_HEADERS {
+            warn!(
+                "Site {} declares more than {} headers",
+                manifest.pubkey, MAX_SITE_HEADERS
+            );
+            break;
+        }
+        total_bytes += name.len() + value.len();
+        if total_bytes > MAX_SITE_HEADERS_BYTES {
+            warn!(
+                "Site {} headers exceed {} bytes",
+                manifest.pubkey, MAX_SITE_HEADERS_BYTES
+            );
+            break;
+        }
+        match parse_header(name, value) {
+            Some((name, value)) => {
+                headers.insert(name, value);
+            }
+            None => warn!("Ignoring site header {:?} from {}", name, manifest.pubkey),
+        }
+    }
+    headers
+}
+
+fn parse_header(name: &str, value: &str) -> Option<(HeaderName, HeaderValue)> {
+    let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+    if is_denied(&name) || value.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    let value = HeaderValue::from_str(value).ok()?;
+    Some((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    fn manifest(headers: &[(&str, &str)]) -> Event {
+        let tags = headers
+            .iter()
+            .map(|(n, v)| Tag::parse(["header", n, v]).unwrap());
+        EventBuilder::new(Kind::Custom(15128), "")
+            .tags(tags)
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_site_headers() {
+        let headers = site_headers(&manifest(&[
+            ("X-Frame-Options", "DENY"),
+            ("Content-Security-Policy", "default-src 'self'"),
+        ]));
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-frame-options"], "DENY");
+    }
+
+    #[test]
+    fn test_site_headers_rejects_malicious() {
+        let headers = site_headers(&manifest(&[
+            ("Host", "evil.example"),
+            ("Transfer-Encoding", "chunked"),
+            ("Access-Control-Allow-Origin", "*"),
+            ("Set-Cookie", "session=1"),
+            ("X-Injected", "a\r\nSet-Cookie: session=1"),
+            ("X-Tab", "a\tb"),
+            ("Bad Name", "1"),
+            ("X-Ok", "1"),
+        ]));
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key("x-ok"));
+    }
+
+    #[test]
+    fn test_site_headers_bounded() {
+        let many: Vec<(String, String)> = (0..100)
+            .map(|i| (format!("X-H{}", i), "1".into()))
+            .collect();
+        let many: Vec<(&str, &str)> = many.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+        assert_eq!(site_headers(&manifest(&many)).len(), MAX_SITE_HEADERS);
+
+        let big = "a".repeat(MAX_SITE_HEADERS_BYTES);
+        let headers = site_headers(&manifest(&[
+            ("X-Ok", "1"),
+            ("X-Big", &big),
+            ("X-After", "1"),
+        ]));
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key("x-ok"));
+    }
+}
@@ -0,0 +1,346 @@
+use axum::http::{HeaderValue, Request, StatusCode, Uri, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Which host wins when the `Host` header and the request authority disagree
+///
+/// HTTP/2 clients carry the host in the `:authority` pseudo-header, which hyper exposes
+/// as the URI authority, the same as an HTTP/1.1 absolute-form request target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HostConflict {
+    /// Use the `Host` header
+    #[default]
+    Host,
+    /// Use the `:authority` pseudo-header
+    Authority,
+    /// Refuse the request with 400
+    Reject,
+}
+
+/// What to do with requests for a host with a leading `www.` label, for `--www`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WwwPrefix {
+    /// Serve the site of the host without `www.` on both
+    Strip,
+    /// Redirect to the host without `www.` with 301
+    Redirect,
+}
+
+/// What to do with fully qualified hosts ending in a dot, like `npub1....example.com.`,
+/// for `--trailing-dot`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrailingDot {
+    /// Serve them like the host without the dot
+    #[default]
+    Strip,
+    /// Refuse them with 400, like any other empty label
+    Reject,
+}
+
+/// Host a request is addressed to, from the `Host` header or the request authority
+///
+/// Either one alone is used as is, when both are present and differ `conflict` decides.
+/// Hosts with an empty or blank label are refused with 400.
+pub fn request_host<B>(request: &Request<B>, conflict: HostConflict) -> Result<&str, StatusCode> {
+    let host = pick_host(request, conflict)?;
+    if !has_valid_labels(host) {
+        log::info!("Refusing request for malformed host {:?}", host);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(host)
+}
+
+fn pick_host<B>(request: &Request<B>, conflict: HostConflict) -> Result<&str, StatusCode> {
+    let host = match request.headers().get(header::HOST) {
+        Some(h) => Some(h.to_str().map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let authority = request.uri().authority().map(|a| a.as_str());
+    match (host, authority) {
+        (Some(host), Some(authority)) if !host.eq_ignore_ascii_case(authority) => match conflict {
+            HostConflict::Host => Ok(host),
+            HostConflict::Authority => Ok(authority),
+            HostConflict::Reject => {
+                log::info!(
+                    "Refusing request with Host {} for authority {}",
+                    host,
+                    authority
+                );
+                Err(StatusCode::BAD_REQUEST)
+            }
+        },
+        (Some(host), _) => Ok(host),
+        (None, Some(authority)) => Ok(authority),
+        (None, None) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Host without its leading `www.` label, `None` when it has none
+pub fn without_www(host: &str) -> Option<&str> {
+    let (label, rest) = host.split_once('.')?;
+    (label.eq_ignore_ascii_case("www") && !rest.is_empty()).then_some(rest)
+}
+
+/// Host sites are resolved from, `host` without `www.` when `www` handling is enabled
+pub fn site_host(host: &str, www: Option<WwwPrefix>) -> &str {
+    match www {
+        Some(_) => without_www(host).unwrap_or(host),
+        None => host,
+    }
+}
+
+/// Host without the single trailing dot of a fully qualified name, port kept, `None` when
+/// it doesn't end in exactly one dot
+pub fn without_trailing_dot(host: &str) -> Option<String> {
+    if host.starts_with('[') {
+        return None;
+    }
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    let name = name
+        .strip_suffix('.')
+        .filter(|n| !n.is_empty() && !n.ends_with('.'))?;
+    Some(match port {
+        Some(port) => format!("{}:{}", name, port),
+        None => name.to_string(),
+    })
+}
+
+/// Drop the trailing dot of the `Host` header and the request authority, so the rest of
+/// the gateway only ever sees `npub1....example.com`, for `--trailing-dot strip`
+pub async fn strip_trailing_dot(mut request: Request<axum::body::Body>, next: Next) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok());
+    if let Some(host) = host.and_then(without_trailing_dot) {
+        match HeaderValue::from_str(&host) {
+            Ok(value) => request.headers_mut().insert(header::HOST, value),
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+    }
+    if let Some(authority) = request
+        .uri()
+        .authority()
+        .and_then(|a| without_trailing_dot(a.as_str()))
+    {
+        let mut parts = request.uri().clone().into_parts();
+        parts.authority = match authority.parse() {
+            Ok(a) => Some(a),
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        *request.uri_mut() = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+    }
+    next.run(request).await
+}
+
+/// Whether every dot separated label of a host, port aside, has something in it
+///
+/// `.example.com`, `a..example.com` and `example.com.` all have an empty label, which
+/// would otherwise be taken for a subdomain. IPv6 literals are left alone.
+fn has_valid_labels(host: &str) -> bool {
+    if host.starts_with('[') {
+        return true;
+    }
+    let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    name.split('.')
+        .all(|label| !label.is_empty() && !label.chars().any(|c| c.is_whitespace()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Version;
+
+    fn h2_request(authority: &str, host: Option<&str>) -> Request<()> {
+        let mut builder =
+            Request::get(format!("https://{}/index.html", authority)).version(Version::HTTP_2);
+        if let Some(host) = host {
+            builder = builder.header(header::HOST, host);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_request_host() {
+        let http1 = Request::get("/")
+            .header(header::HOST, "a.example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            request_host(&http1, HostConflict::Reject),
+            Ok("a.example.com")
+        );
+
+        let h2 = h2_request("npub1abc.example.com:443", None);
+        for conflict in [
+            HostConflict::Host,
+            HostConflict::Authority,
+            HostConflict::Reject,
+        ] {
+            assert_eq!(request_host(&h2, conflict), Ok("npub1abc.example.com:443"));
+        }
+        let host = request_host(&h2, HostConflict::default()).unwrap();
+        assert_eq!(crate::site::subdomain(host), Some("npub1abc"));
+
+        let same = h2_request("A.example.com", Some("a.example.com"));
+        assert_eq!(
+            request_host(&same, HostConflict::Reject),
+            Ok("a.example.com")
+        );
+
+        let missing = Request::get("/").body(()).unwrap();
+        assert_eq!(
+            request_host(&missing, HostConflict::Host),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_request_host_conflict() {
+        let request = h2_request("a.example.com", Some("b.example.com"));
+        assert_eq!(
+            request_host(&request, HostConflict::Host),
+            Ok("b.example.com")
+        );
+        assert_eq!(
+            request_host(&request, HostConflict::Authority),
+            Ok("a.example.com")
+        );
+        assert_eq!(
+            request_host(&request, HostConflict::Reject),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_without_www() {
+        assert_eq!(
+            without_www("www.npub1abc.example.com"),
+            Some("npub1abc.example.com")
+        );
+        assert_eq!(
+            without_www("WWW.example.com:3000"),
+            Some("example.com:3000")
+        );
+        for host in [
+            "npub1abc.example.com",
+            "www",
+            "wwwx.example.com",
+            "a.www.example.com",
+        ] {
+            assert_eq!(without_www(host), None, "{}", host);
+        }
+        assert_eq!(
+            site_host("www.a.example.com", Some(WwwPrefix::Strip)),
+            "a.example.com"
+        );
+        assert_eq!(site_host("www.a.example.com", None), "www.a.example.com");
+    }
+
+    #[test]
+    fn test_malformed_hosts() {
+        for host in [
+            ".example.com",
+            "a..example.com",
+            "example.com.",
+            "a.example.com.:80",
+            "a. .example.com",
+            "",
+        ] {
+            let request = Request::get("/")
+                .header(header::HOST, host)
+                .body(())
+                .unwrap();
+            assert_eq!(
+                request_host(&request, HostConflict::Host),
+                Err(StatusCode::BAD_REQUEST),
+                "{}",
+                host
+            );
+        }
+        for host in [
+            "a.example.com:3000",
+            "localhost",
+            "127.0.0.1:3000",
+            "[::1]:3000",
+        ] {
+            let request = Request::get("/")
+                .header(header::HOST, host)
+                .body(())
+                .unwrap();
+            assert_eq!(request_host(&request, HostConflict::Host), Ok(host));
+        }
+    }
+
+    #[test]
+    fn test_without_trailing_dot() {
+        assert_eq!(
+            without_trailing_dot("npub1abc.example.com.").as_deref(),
+            Some("npub1abc.example.com")
+        );
+        assert_eq!(
+            without_trailing_dot("a.example.com.:8080").as_deref(),
+            Some("a.example.com:8080")
+        );
+        for host in [
+            "a.example.com",
+            "a.example.com:8080",
+            "example.com..",
+            ".",
+            "[::1]:80",
+        ] {
+            assert_eq!(without_trailing_dot(host), None, "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strip_trailing_dot() {
+        use axum::Router;
+        use axum::body::Body;
+        use axum::routing::get;
+        use tower::ServiceExt;
+
+        let app = Router::new()
+            .fallback(get(|request: Request<Body>| async move {
+                let host = request_host(&request, HostConflict::Reject).map(|h| h.to_string());
+                format!(
+                    "{:?}",
+                    host.ok().as_deref().and_then(crate::site::subdomain)
+                )
+            }))
+            .layer(axum::middleware::from_fn(strip_trailing_dot));
+        let get = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let http1 = Request::get("/")
+            .header(header::HOST, "npub1abc.example.com.")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(get(http1).await, "Some(\"npub1abc\")");
+        let h2 = Request::get("https://npub1abc.example.com.:443/")
+            .version(Version::HTTP_2)
+            .header(header::HOST, "npub1abc.example.com.:443")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(get(h2).await, "Some(\"npub1abc\")");
+        let doubled = Request::get("/")
+            .header(header::HOST, "npub1abc.example.com..")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(get(doubled).await, "None");
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Grid size of generated identicons
+const GRID: usize = 5;
+
+/// Maximum number of identicons kept in memory
+const MAX_CACHED: usize = 10_000;
+
+/// Generated identicons keyed by pubkey
+static IDENTICONS: once_cell::sync::Lazy<Mutex<HashMap<[u8; 32], Arc<str>>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Get the identicon for a pubkey, generating it on first use
+pub fn identicon(pubkey: &[u8; 32]) -> Arc<str> {
+    let mut cache = IDENTICONS.lock().unwrap();
+    if let Some(svg) = cache.get(pubkey) {
+        return svg.clone();
+    }
+    if cache.len() >= MAX_CACHED {
+        cache.clear();
+    }
+    let svg: Arc<str> = identicon_svg(pubkey).into();
+    cache.insert(*pubkey, svg.clone());
+    svg
+}
+
+/// Which cells of the (horizontally mirrored) grid are filled
+fn cells(pubkey: &[u8; 32]) -> [[bool; GRID]; GRID] {
+    let mut cells = [[false; GRID]; GRID];
+    let half = GRID.div_ceil(2);
+    for (row, line) in cells.iter_mut().enumerate() {
+        for col in 0..half {
+            let on = pubkey[2 + row * half + col] & 1 == 1;
+            line[col] = on;
+            line[GRID - 1 - col] = on;
+        }
+    }
+    cells
+}
+
+/// Render a deterministic GitHub-style identicon derived from the pubkey bytes
+fn identicon_svg(pubkey: &[u8; 32]) -> String {
+    let hue = u16::from_be_bytes([pubkey[0], pubkey[1]]) % 360;
+    let mut svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-0.5 -0.5 {0} {0}"><rect x="-0.5" y="-0.5" width="{0}" height="{0}" fill="#f0f0f0"/><g fill="hsl({1}, 55%, 55%)">"##,
+        GRID + 1,
+        hue
+    );
+    for (y, line) in cells(pubkey).iter().enumerate() {
+        for (x, on) in line.iter().enumerate() {
+            if *on {
+                let _ = write!(svg, r#"<rect x="{}" y="{}" width="1" height="1"/>"#, x, y);
+            }
+        }
+    }
+    svg.push_str("</g></svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identicon_deterministic() {
+        let a = [7u8; 32];
+        let mut b = [7u8; 32];
+        b[0] = 200;
+
+        assert_eq!(identicon_svg(&a), identicon_svg(&a));
+        assert_ne!(identicon_svg(&a), identicon_svg(&b));
+        assert_eq!(&*identicon(&a), identicon_svg(&a).as_str());
+    }
+
+    #[test]
+    fn test_identicon_mirrored() {
+        let pubkey: [u8; 32] = std::array::from_fn(|i| (i * 37) as u8);
+        for line in cells(&pubkey) {
+            for col in 0..GRID {
+                assert_eq!(line[col], line[GRID - 1 - col]);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::alias::{SiteAlias, is_pattern};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+/// NIP-05 `nostr.json` document for the aliases hosted on this gateway
+///
+/// When `name` is given only that name is returned, names are matched case-insensitively.
+pub fn nostr_json(aliases: &HashMap<String, SiteAlias>, name: Option<&str>) -> Value {
+    let names: serde_json::Map<String, Value> = match name {
+        Some(name) => {
+            let name = name.to_ascii_lowercase();
+            aliases
+                .get(&name)
+                .filter(|_| !is_pattern(&name))
+                .map(|a| (name, json!(hex::encode(a.pubkey))))
+                .into_iter()
+                .collect()
+        }
+        None => aliases
+            .iter()
+            .filter(|(name, _)| !is_pattern(name))
+            .map(|(name, a)| (name.clone(), json!(hex::encode(a.pubkey))))
+            .collect(),
+    };
+    json!({ "names": names })
+}
+
+/// WebFinger (RFC 7033) document for an `acct:<alias>@<domain>` resource
+///
+/// Returns `None` when the resource is not an account on `domain`.
+pub fn webfinger(
+    aliases: &HashMap<String, SiteAlias>,
+    resource: &str,
+    scheme: &str,
+    domain: &str,
+) -> Option<Value> {
+    let (name, resource_domain) = resource.strip_prefix("acct:")?.rsplit_once('@')?;
+    if !resource_domain.eq_ignore_ascii_case(domain) {
+        return None;
+    }
+    let name = name.to_ascii_lowercase();
+    let alias = aliases.get(&name).filter(|_| !is_pattern(&name))?;
+    let site = format!("{}://{}.{}/", scheme, name, domain);
+    Some(json!({
+        "subject": format!("acct:{}@{}", name, domain),
+        "aliases": [site],
+        "properties": {
+            "https://nostr.com/ns/pubkey": hex::encode(alias.pubkey),
+        },
+        "links": [
+            { "rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": site },
+        ],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases() -> HashMap<String, SiteAlias> {
+        let alias = SiteAlias::new([1u8; 32]);
+        HashMap::from([
+            ("alice".to_string(), alias.clone()),
+            ("bob".to_string(), alias),
+        ])
+    }
+
+    #[test]
+    fn test_nostr_json() {
+        let hex = hex::encode([1u8; 32]);
+        let aliases = aliases();
+        assert_eq!(
+            nostr_json(&aliases, Some("Alice")),
+            json!({ "names": { "alice": hex } })
+        );
+        assert_eq!(nostr_json(&aliases, Some("carol")), json!({ "names": {} }));
+        assert_eq!(
+            nostr_json(&aliases, None)["names"]
+                .as_object()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_webfinger() {
+        let aliases = aliases();
+        let doc = webfinger(&aliases, "acct:alice@example.com", "https", "example.com").unwrap();
+        assert_eq!(doc["subject"], "acct:alice@example.com");
+        assert_eq!(doc["aliases"][0], "https://alice.example.com/");
+
+        assert!(webfinger(&aliases, "acct:alice@other.com", "https", "example.com").is_none());
+        assert!(webfinger(&aliases, "acct:carol@example.com", "https", "example.com").is_none());
+        assert!(
+            webfinger(
+                &aliases,
+                "https://example.com/alice",
+                "https",
+                "example.com"
+            )
+            .is_none()
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Resolve and fetch nsites: find a site's manifest on Nostr relays, resolve its routes
+//! and download their blobs from Blossom into a disk cache
+//!
+//! The `nsite-rs` gateway is an HTTP server on top of this crate. Without the default
+//! `server` feature the crate builds without the server's dependencies, for tools that
+//! only need to resolve sites.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub mod access_log;
+pub mod alias;
+pub mod blob_cache;
+pub mod blob_storage;
+pub mod cache_rules;
+pub mod disk_lru;
+pub mod feed;
+pub mod geo;
+pub mod headers;
+pub mod live;
+pub mod memory;
+pub mod metrics;
+pub mod nip05;
+pub mod otlp;
+pub mod prefetch;
+pub mod relay_pools;
+pub mod relays;
+pub mod route_ttl;
+pub mod server_health;
+pub mod server_order;
+pub mod site;
+pub mod site_version;
+pub mod snapshot;
+pub mod sniff;
+pub mod tombstones;
+
+pub use site::{RouteContent, SiteConfig, SiteInfo, SiteRoute};
+
+/// Loaded sites by cache key, see [`site::site_cache_key`]
+pub type SiteMap = Arc<RwLock<HashMap<String, site::SiteInfo>>>;
+
+/// Site aliases by domain
+pub type SiteAliasMap = Arc<RwLock<HashMap<String, alias::SiteAlias>>>;
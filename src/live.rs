@@ -0,0 +1,139 @@
+use crate::SiteMap;
+use crate::site::SiteConfig;
+use log::{info, warn};
+use nostr_sdk::prelude::{Client, Event, Filter, PublicKey, RelayPoolNotification, SubscriptionId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How often subscriptions are matched up with the sites in the cache
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keep cached sites current with a relay subscription per site owner
+///
+/// Each owner is subscribed to their manifests and server list, and newer versions are
+/// applied to the cached sites as they arrive. Owners of newly cached sites are picked up
+/// every few seconds, owners whose sites all left the cache are unsubscribed. Relays are
+/// sent the subscriptions again when they reconnect, answering with the latest stored
+/// events, so updates published meanwhile still arrive. Sites resolved from an alias's
+/// own relays are left to their TTL.
+pub async fn follow_sites(client: Client, site_map: SiteMap, config: Arc<SiteConfig>) {
+    let mut notifications = client.notifications();
+    let mut subscribed: HashMap<PublicKey, SubscriptionId> = HashMap::new();
+    let mut interval = tokio::time::interval(SYNC_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let wanted = owners(site_map.read().await.keys());
+                sync(&client, &config, &mut subscribed, &wanted).await;
+            }
+            notification = notifications.recv() => match notification {
+                Ok(RelayPoolNotification::Event { subscription_id, event, .. }) => {
+                    if subscribed.get(&event.pubkey) == Some(&subscription_id) {
+                        apply(&site_map, &event).await;
+                    }
+                }
+                Ok(RelayPoolNotification::Shutdown) | Err(RecvError::Closed) => return,
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    // Subscribing again has relays send the latest events once more
+                    warn!("Missed {} relay notifications, subscribing to site owners again", missed);
+                    for id in subscribed.values() {
+                        client.unsubscribe(id).await;
+                    }
+                    subscribed.clear();
+                }
+            }
+        }
+    }
+}
+
+/// Subscribe to owners in `wanted` not subscribed yet and unsubscribe the rest
+async fn sync(
+    client: &Client,
+    config: &SiteConfig,
+    subscribed: &mut HashMap<PublicKey, SubscriptionId>,
+    wanted: &HashSet<PublicKey>,
+) {
+    let gone: Vec<PublicKey> = subscribed
+        .keys()
+        .filter(|p| !wanted.contains(*p))
+        .copied()
+        .collect();
+    for owner in gone {
+        if let Some(id) = subscribed.remove(&owner) {
+            client.unsubscribe(&id).await;
+        }
+    }
+    let mut added = 0;
+    for owner in wanted {
+        if subscribed.contains_key(owner) {
+            continue;
+        }
+        let id = SubscriptionId::new(format!("nsite-{}", &owner.to_hex()[..16]));
+        let filter = Filter::new().author(*owner).kinds([
+            config.root_site_kind,
+            config.named_site_kind,
+            config.server_list_kind,
+        ]);
+        match client.subscribe_with_id(id.clone(), filter, None).await {
+            Ok(_) => {
+                subscribed.insert(*owner, id);
+                added += 1;
+            }
+            Err(e) => warn!("Failed to subscribe to site owner {}: {}", owner, e),
+        }
+    }
+    if added > 0 {
+        info!(
+            "Following {} site owners for live updates",
+            subscribed.len()
+        );
+    }
+}
+
+/// Hand an event to every cached site of its author resolved from the gateway's relays
+async fn apply(site_map: &SiteMap, event: &Event) {
+    let owner = event.pubkey.to_hex();
+    let sites: Vec<_> = site_map
+        .read()
+        .await
+        .iter()
+        .filter(|(key, _)| key.starts_with(&owner) && !key.contains('~'))
+        .map(|(_, site)| site.clone())
+        .collect();
+    for site in sites {
+        site.apply_live_event(event).await;
+    }
+}
+
+/// Owners of the sites in the cache that can be updated live
+///
+/// Pinned sites never change and alias sites use their own relays, so neither is followed.
+fn owners<'a>(keys: impl Iterator<Item = &'a String>) -> HashSet<PublicKey> {
+    keys.filter(|key| !key.contains(['@', '~']))
+        .filter_map(|key| PublicKey::from_hex(key.get(..64)?).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owners() {
+        let a = hex::encode([1u8; 32]);
+        let b = hex::encode([2u8; 32]);
+        let keys = [
+            a.clone(),
+            format!("{}-blog", a),
+            format!("{}@{}", b, hex::encode([9u8; 32])),
+            format!("{}~0123456789ab", b),
+            "not-a-site".to_string(),
+        ];
+        let owners = owners(keys.iter());
+        assert_eq!(owners.len(), 1);
+        assert!(owners.contains(&PublicKey::from_hex(&a).unwrap()));
+    }
+}
@@ -1,31 +1,95 @@
 use anyhow::Result;
 use axum::{
     Router,
+    body::Body,
     extract::State,
-    http::{StatusCode, header},
+    http::{HeaderValue, StatusCode, header},
     response::Response,
     routing::get,
 };
 use clap::Parser;
-use log::{error, info};
-use nostr_sdk::Client;
+use log::{error, info, warn};
+use nostr_sdk::{Client, Kind, RelayUrl, Timestamp, Url};
 use std::{
     collections::HashMap,
+    fs::Permissions,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
 
-mod site;
+use nsite::sniff::extension_content_type;
+use nsite::{
+    SiteAliasMap, SiteMap, access_log, alias, blob_cache, blob_storage, disk_lru, feed, geo, live,
+    memory, metrics, nip05, otlp, prefetch, relay_pools, relays, route_ttl, server_health,
+    server_order, site, site_version, snapshot, sniff, tombstones,
+};
+
+mod base_path;
+mod cache_control;
+mod canonical_index;
+mod capabilities;
+mod compression;
+mod etag;
+mod gateway;
+mod host;
+mod identicon;
+mod identity;
+mod methods;
+mod range;
+mod resize;
+mod rewrite;
+mod sitemap;
+mod sri;
+mod tls;
+mod tls_ask;
 
 const INDEX_HTML: &str = include_str!("index.html");
 
-type SiteMap = Arc<RwLock<HashMap<String, site::SiteInfo>>>;
-type SiteAliasMap = Arc<RwLock<HashMap<String, [u8; 32]>>>;
+/// Directory page linking to sites by path instead of subdomain, for --gateway
+static GATEWAY_INDEX_HTML: once_cell::sync::Lazy<String> = once_cell::sync::Lazy::new(|| {
+    INDEX_HTML.replacen(
+        "<head>",
+        "<head>\n    <meta name=\"nsite-routing\" content=\"path\">",
+        1,
+    )
+});
+const COMING_SOON_HTML: &str = include_str!("coming_soon.html");
+const RECONNECTING_HTML: &str = include_str!("reconnecting.html");
+
+/// Seconds visitors are asked to wait while the relays reconnect
+const RECONNECTING_RETRY_AFTER: &str = "30";
+
+/// Relays used when none are configured
+const DEFAULT_RELAYS: [&str; 4] = [
+    "wss://relay.damus.io",
+    "wss://relay.snort.social",
+    "wss://relay.primal.net",
+    "wss://nos.lol",
+];
+
+/// Where security researchers look for a security.txt, relative to the site root
+const SECURITY_TXT: &str = ".well-known/security.txt";
+
+/// Response header carrying the version a site declares, for `--site-versions`
+const SITE_VERSION: &str = "x-nsite-version";
+
+/// Request header with the oldest site version a client accepts
+const SITE_MIN_VERSION: &str = "x-nsite-min-version";
+
+/// robots.txt served for `noindex` aliases
+const NOINDEX_ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+
+/// Most assets preloaded from one page with --preload-links
+const PRELOAD_LINKS_LIMIT: usize = 16;
 
 /// NSite proxy
 #[derive(Parser)]
@@ -33,6 +97,512 @@ type SiteAliasMap = Arc<RwLock<HashMap<String, [u8; 32]>>>;
 struct Args {
     #[arg(long, short)]
     pub relay: Vec<String>,
+
+    /// File listing relays, one per line. Reloaded on SIGHUP without dropping the site cache
+    #[arg(long)]
+    pub relay_file: Option<PathBuf>,
+
+    /// JSON file mapping subdomain aliases to sites
+    #[arg(long)]
+    pub alias_file: Option<PathBuf>,
+
+    /// JSON file mapping short numeric IDs or slugs to root sites, e.g. 42.example.com
+    #[arg(long)]
+    pub registry_file: Option<PathBuf>,
+
+    /// security.txt served at /.well-known/security.txt on the root domain and on every
+    /// site that doesn't publish its own
+    #[arg(long, value_name = "FILE")]
+    pub security_txt: Option<PathBuf>,
+
+    /// HTML page served for reserved aliases whose site isn't published yet
+    #[arg(long)]
+    pub coming_soon_file: Option<PathBuf>,
+
+    /// Status of the coming-soon page, 200 or 503
+    #[arg(long, default_value_t = 503, value_parser = parse_coming_soon_status)]
+    pub coming_soon_status: u16,
+
+    /// While every relay is disconnected, answer sites that aren't cached with a
+    /// reconnecting page (503 with Retry-After) instead of waiting for the relays to time
+    /// out. Cached sites keep being served
+    #[arg(long)]
+    pub reconnecting_page: bool,
+
+    /// HTML page served by --reconnecting-page instead of the built-in one
+    #[arg(long, requires = "reconnecting_page")]
+    pub reconnecting_file: Option<PathBuf>,
+
+    /// Resize images on the fly when requested with `?w=<width>`
+    #[arg(long)]
+    pub image_resize: bool,
+
+    /// Largest width that may be requested when resizing images
+    #[arg(long, default_value_t = 2048)]
+    pub image_max_width: u32,
+
+    /// Blossom server tried after a site's own servers, can be repeated
+    #[arg(long)]
+    pub fallback_blossom_server: Vec<Url>,
+
+    /// Follow mirror locations (`Link: rel="duplicate"`) advertised by servers missing a
+    /// blob, up to this many hops. What a mirror serves is only used if its hash matches
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=5))]
+    pub blob_mirror_hops: u8,
+
+    /// Blossom server asked with `HEAD /<sha256>` for blobs none of a site's servers or
+    /// mirrors have, can be repeated. Servers that have the blob are downloaded from
+    #[arg(long)]
+    pub blob_discovery_server: Vec<Url>,
+
+    /// Ask this Blossom server to mirror (BUD-04) blobs only found on a discovery server
+    #[arg(long, requires = "blob_mirror_key")]
+    pub blob_mirror_to: Option<Url>,
+
+    /// Secret key, nsec or hex, the mirror requests of `--blob-mirror-to` are signed with
+    #[arg(long)]
+    pub blob_mirror_key: Option<String>,
+
+    /// Most blobs of one site owner downloaded from Blossom at once, so a page with many
+    /// assets can't starve other sites of downloads
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub per_site_download_concurrency: Option<u16>,
+
+    /// Also query the relays a site's Blossom server list names in `relay` tags, up to 4
+    /// per site. Only public `wss://` relays are used
+    #[arg(long)]
+    pub server_list_relay_hints: bool,
+
+    /// Look up the NIP-65 relay list of each site's owner and also query the relays it
+    /// writes to, up to 4 per site. Only public `wss://` relays are used
+    #[arg(long)]
+    pub outbox_relays: bool,
+
+    /// Compress responses when the client accepts gzip or brotli
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Responses smaller than this are never compressed
+    #[arg(long, default_value_t = 1024)]
+    pub compress_min_bytes: u16,
+
+    /// Encodings compressed responses may use, picked from the client's Accept-Encoding.
+    /// Can be repeated
+    #[arg(long, value_enum, value_name = "ENCODING", default_values_t = [compression::Encoding::Br, compression::Encoding::Gzip])]
+    pub compress_encoding: Vec<compression::Encoding>,
+
+    /// Send Cache-Control headers: revalidated HTML, immutable fingerprinted assets
+    #[arg(long)]
+    pub cache_control: bool,
+
+    /// Seconds HTML may be cached before browsers revalidate it, with --cache-control
+    #[arg(long, value_name = "SECS", default_value_t = cache_control::MaxAges::default().html)]
+    pub html_max_age: u64,
+
+    /// Seconds fingerprinted assets like `app.3f9a2b1c.js` may be cached, with --cache-control
+    #[arg(long, value_name = "SECS", default_value_t = cache_control::MaxAges::default().immutable)]
+    pub immutable_max_age: u64,
+
+    /// Seconds every other file may be cached, with --cache-control
+    #[arg(long, value_name = "SECS", default_value_t = cache_control::MaxAges::default().other)]
+    pub asset_max_age: u64,
+
+    /// Honor the max-ages sites declare in `cache` tags of their manifest, with
+    /// --cache-control, capped at this many seconds
+    #[arg(long, value_name = "SECS", requires = "cache_control")]
+    pub site_max_age_cap: Option<u64>,
+
+    /// Serve a generated identicon as the favicon of sites that don't publish one
+    #[arg(long)]
+    pub identicon_favicon: bool,
+
+    /// Expose Prometheus metrics at /metrics on the root domain
+    #[arg(long)]
+    pub metrics: bool,
+
+    /// Serve Prometheus metrics at /metrics on this separate address only, e.g.
+    /// 127.0.0.1:9100, keeping them off the public listeners unless --metrics is also set
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Export a trace of every request, with its relay queries and Blossom downloads as
+    /// spans, to this OTLP/HTTP collector, e.g. http://localhost:4318
+    #[cfg(feature = "otlp")]
+    #[arg(long, value_name = "URL")]
+    pub otlp_endpoint: Option<Url>,
+
+    /// Continue the trace of a `traceparent` header sent with a request. Only enable this
+    /// behind a proxy that sets or strips the header, clients could pick trace ids otherwise
+    #[cfg(feature = "otlp")]
+    #[arg(long, requires = "otlp_endpoint")]
+    pub otlp_trust_traceparent: bool,
+
+    /// Serve a JSON Feed of the manifest versions the gateway saw for a site at
+    /// /_nsite/feed.json on the site, newest first
+    #[arg(long)]
+    pub update_feed: bool,
+
+    /// Answer paths that an earlier manifest of a site published and a newer one removed
+    /// with 410 Gone instead of 404, so crawlers drop them. Only removals seen by this
+    /// gateway since it started count
+    #[arg(long)]
+    pub gone_removed_routes: bool,
+
+    /// Subscribe to the owners of cached sites on the relays and apply new manifests and
+    /// server lists as they're published, instead of only when a site's TTL runs out
+    #[arg(long)]
+    pub live_updates: bool,
+
+    /// Serve /index.html for unknown paths without a file extension, so single-page apps
+    /// can handle their routes client-side. Missing files like /logo.png still get 404
+    #[arg(long)]
+    pub spa: bool,
+
+    /// Resolve sites from the first path segment, http://host/npub1.../path, instead of
+    /// the subdomain, for gateways without wildcard DNS. Sites should use relative links,
+    /// absolute ones like /style.css point at the gateway root
+    #[arg(long)]
+    pub gateway: bool,
+
+    /// Give directory indexes one URL, redirecting the other with 301: `slash` sends
+    /// /index.html to /, `index-html` sends / to /index.html. Both are served when unset
+    #[arg(long, value_enum, value_name = "URL")]
+    pub canonical_index: Option<canonical_index::CanonicalIndex>,
+
+    /// Generate /sitemap.xml for sites that don't publish their own
+    #[arg(long)]
+    pub generate_sitemap: bool,
+
+    /// Apply response headers declared by sites with `header` manifest tags
+    #[arg(long)]
+    pub site_headers: bool,
+
+    /// Send the version sites declare with a `version` manifest tag as X-Nsite-Version,
+    /// and answer 409 to clients asking for a newer one with X-Nsite-Min-Version
+    #[arg(long)]
+    pub site_versions: bool,
+
+    /// Serve NIP-05 (/.well-known/nostr.json) and WebFinger identities for aliases on the root domain
+    #[arg(long)]
+    pub nip05: bool,
+
+    /// Describe the gateway's configuration and enabled features at /.well-known/nsite.json
+    /// on the root domain, for tooling and site owners
+    #[arg(long)]
+    pub capabilities: bool,
+
+    /// Serve JSON endpoints under /_nsite/ on every site, e.g. /_nsite/ls?prefix=/docs/
+    /// and /_nsite/route?path=/index.html
+    #[arg(long)]
+    pub site_api: bool,
+
+    /// Base domain sites are served under, enables the on-demand TLS ask endpoint
+    /// at /_nsite/tls-ask?domain=<hostname> on the root domain for these domains
+    #[arg(long)]
+    pub tls_ask_domain: Vec<String>,
+
+    /// Maximum number of new hostnames approved for certificates per hour
+    #[arg(long, default_value_t = 30)]
+    pub tls_ask_max_per_hour: u32,
+
+    /// Timeout in seconds for fetching site manifests when resolving routes
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub route_timeout: u64,
+
+    /// Timeout in seconds for fetching a site's Blossom server list
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub server_list_timeout: u64,
+
+    /// Answer requests for a site that failed to load with the same error for this many
+    /// seconds instead of asking the relays again. A request with `Cache-Control: no-cache`
+    /// retries right away
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub failed_site_ttl: u64,
+
+    /// Re-check the manifest for routes of a content class once this many seconds have
+    /// passed, e.g. --route-ttl html=60 --route-ttl image=86400, or for routes of every
+    /// class without a TTL of its own with a bare --route-ttl 300.
+    /// Classes are html, css, js, image and other
+    #[arg(long, value_name = "[CLASS=]SECS", value_parser = route_ttl::parse_ttl_spec)]
+    pub route_ttl: Vec<(Option<route_ttl::ContentClass>, Duration)>,
+
+    /// Files larger than this are streamed rather than buffered, and cold blobs larger
+    /// than this are streamed from Blossom without being written to the disk cache
+    #[arg(long, value_name = "BYTES")]
+    pub stream_threshold_bytes: Option<u64>,
+
+    /// Directory downloaded blobs are cached in, created if missing. Defaults to nsite
+    /// in the system temp directory, which may be wiped on reboot
+    #[arg(long, value_name = "PATH")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Serve a blob already cached for a path with another extension from a hard link to
+    /// that file, instead of downloading and storing it again
+    #[arg(long)]
+    pub link_duplicate_blobs: bool,
+
+    /// Keep the blobs in the disk cache under this many bytes, deleting the least recently
+    /// used ones after writes. Unlimited when unset
+    #[arg(long, value_name = "BYTES")]
+    pub cache_max_bytes: Option<u64>,
+
+    /// Where downloaded blobs are kept: only in --cache-dir, or also shared with other
+    /// gateways through Redis at --redis-url (needs the `redis` cargo feature)
+    #[arg(long, value_enum, default_value_t = blob_cache::CacheBackend::Disk)]
+    #[cfg_attr(feature = "redis", arg(requires_if("redis", "redis_url")))]
+    pub cache_backend: blob_cache::CacheBackend,
+
+    /// Redis to share blobs through with --cache-backend redis,
+    /// e.g. redis://:password@cache.internal:6379/0
+    #[cfg(feature = "redis")]
+    #[arg(long, value_name = "URL")]
+    pub redis_url: Option<Url>,
+
+    /// Expire blobs shared through Redis after this many seconds, 0 keeps them until
+    /// Redis evicts them
+    #[cfg(feature = "redis")]
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub redis_ttl: u64,
+
+    /// Keep the in-memory site cache (manifests, resolved routes and server lists) under
+    /// roughly this many bytes, dropping the routes and then whole sites idle the longest
+    #[arg(long, value_name = "BYTES")]
+    pub max_memory_bytes: Option<usize>,
+
+    /// Always stream cold blobs of this content type from Blossom without caching them,
+    /// e.g. video/* or audio/mpeg. Can be repeated
+    #[arg(long, value_name = "TYPE", value_parser = blob_storage::parse_pattern)]
+    pub stream_content_type: Vec<String>,
+
+    /// Always cache blobs of this content type, even above --stream-threshold-bytes.
+    /// Wins over --stream-content-type unless that names the type more exactly. Can be repeated
+    #[arg(long, value_name = "TYPE", value_parser = blob_storage::parse_pattern)]
+    pub cache_content_type: Vec<String>,
+
+    /// Download a site's index.html while resolving it, trading slower site loads for a
+    /// cached first page view
+    #[arg(long)]
+    pub eager_index: bool,
+
+    /// Resolve every path of a site's manifest when the site is loaded, for manifests with
+    /// at most this many paths. Later requests skip the manifest lookup
+    #[arg(long, value_name = "MAX_ROUTES")]
+    pub preload_routes: Option<usize>,
+
+    /// Download the assets an HTML page references in the background when it is served
+    #[arg(long)]
+    pub prefetch: bool,
+
+    /// Resolve the routes of same-site pages an HTML page links to in the background,
+    /// without downloading them, so navigation skips the manifest lookup
+    #[arg(long)]
+    pub prefetch_links: bool,
+
+    /// How many levels of links --prefetch-links follows, through pages already cached
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+    pub prefetch_link_depth: u8,
+
+    /// Longest request path accepted in bytes, as sent before decoding. Longer paths are
+    /// refused with 414 before resolving the site
+    #[arg(long, default_value_t = 2048)]
+    pub max_path_length: usize,
+
+    /// Maximum number of routes resolved for a single page request, including prefetched
+    /// assets. Assets over the limit are loaded when the browser requests them
+    #[arg(long, default_value_t = 32)]
+    pub max_routes_per_request: usize,
+
+    /// Header carrying the client country set by a CDN (e.g. CF-IPCountry), enables
+    /// the geo rules sites declare with `geo` manifest tags
+    #[arg(long, value_name = "HEADER")]
+    pub geo_header: Option<String>,
+
+    /// Retry missing blobs of manifests published less than this long ago, covering the
+    /// gap between publishing a site and its uploads reaching Blossom
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub publish_grace: u64,
+
+    /// How long to keep retrying a missing blob within the publish grace window
+    #[arg(long, value_name = "SECS", default_value_t = 20)]
+    pub publish_retry: u64,
+
+    /// Event kind of root site manifests
+    #[arg(long, default_value_t = site::ROOT_SITE_KIND)]
+    pub root_site_kind: u16,
+
+    /// Event kind of named site manifests
+    #[arg(long, default_value_t = site::NAMED_SITE_KIND)]
+    pub named_site_kind: u16,
+
+    /// Event kind of Blossom server lists
+    #[arg(long, default_value_t = site::SERVER_LIST_KIND)]
+    pub server_list_kind: u16,
+
+    /// Hash every served file in the background and log any mismatch with the hash
+    /// its route expects, counted in the metrics as nsite_debug_verify_total
+    #[arg(long)]
+    pub debug_verify_log: bool,
+
+    /// Resolve root sites from hex pubkeys split into two 32 character subdomains, as
+    /// 64 characters don't fit in one DNS label
+    #[arg(long)]
+    pub hex_subdomains: bool,
+
+    /// Which host to use when the Host header and the HTTP/2 :authority disagree
+    #[arg(long, value_enum, default_value_t = host::HostConflict::Host)]
+    pub host_conflict: host::HostConflict,
+
+    /// Handle hosts with a leading `www.`, like www.npub1....example.com, as the host without
+    /// it, either serving its site on both or redirecting to it
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub www: Option<host::WwwPrefix>,
+
+    /// Hosts ending in a dot, like npub1....example.com., are served like the host without
+    /// it, or refused with 400
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = host::TrailingDot::Strip)]
+    pub trailing_dot: host::TrailingDot,
+
+    /// Log every request with the `access` log target, sites can opt out or anonymize
+    /// their visitors with `access_log` in the alias file
+    #[arg(long)]
+    pub access_log: bool,
+
+    /// Refuse to resolve sites with 503 unless at least this many relays answered the
+    /// query, so one reachable relay alone can't serve a stale or forged manifest
+    #[arg(long, default_value_t = 0)]
+    pub min_relay_responses: usize,
+
+    /// NIP-05 domain to look up subdomains without an alias at, e.g. `example.com` to
+    /// serve `alice.<gateway>` from alice@example.com. Can be repeated, up to 4 are
+    /// tried in order and the first that knows the name wins
+    #[arg(long)]
+    pub nip05_provider: Vec<String>,
+
+    /// Total seconds allowed for looking a name up at the NIP-05 providers
+    #[arg(long, default_value_t = 5)]
+    pub nip05_timeout: u64,
+
+    /// Look names found at a NIP-05 provider up again once this many seconds have passed,
+    /// dropping those no provider lists anymore. 0 keeps them until restart
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub nip05_ttl: u64,
+
+    /// Largest nostr.json read from a NIP-05 provider, larger answers count as failures
+    #[arg(long, value_name = "BYTES", default_value_t = nip05::FetchLimits::default().max_bytes)]
+    pub nip05_max_bytes: usize,
+
+    /// Allow NIP-05 providers on loopback, private or link-local addresses, which are
+    /// refused by default so names can't be used to reach internal services
+    #[arg(long)]
+    pub nip05_allow_private: bool,
+
+    /// Order a site's Blossom servers are tried in: the order the site publishes them in,
+    /// fastest measured first, rotating between them, all at once, or most reliable first
+    #[arg(long, value_enum, default_value_t = server_order::ServerOrdering::Owner)]
+    pub server_ordering: server_order::ServerOrdering,
+
+    /// Skip a Blossom server for --server-breaker-cooldown after it failed this many
+    /// downloads within --server-breaker-window, then let one download probe it
+    #[arg(long, value_name = "FAILURES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub server_breaker_failures: Option<u32>,
+
+    /// Seconds over which Blossom server failures are counted
+    #[arg(long, default_value_t = 60, requires = "server_breaker_failures")]
+    pub server_breaker_window: u64,
+
+    /// Seconds a failing Blossom server is skipped before it's probed again
+    #[arg(long, default_value_t = 30, requires = "server_breaker_failures")]
+    pub server_breaker_cooldown: u64,
+
+    /// Add `Link: rel=preload` headers for the assets of cached HTML pages. No 103 Early
+    /// Hints response is sent, a CDN supporting them can send one from these
+    #[arg(long)]
+    pub preload_links: bool,
+
+    /// Add `integrity` attributes to the scripts and stylesheets of HTML pages that the
+    /// site manifest publishes, so browsers check them against their hash
+    #[arg(long)]
+    pub inject_sri: bool,
+
+    /// Serve only the sites in this snapshot directory, without connecting to any
+    /// relay or Blossom server
+    #[arg(long, conflicts_with = "export_snapshot")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Write the --export-site sites and their blobs to this snapshot directory and exit
+    #[arg(long, requires = "export_site")]
+    pub export_snapshot: Option<PathBuf>,
+
+    /// Site to export, as `<npub or hex pubkey>[:<identifier>]`, can be repeated
+    #[arg(long)]
+    pub export_site: Vec<String>,
+
+    /// Address to serve plain HTTP on, e.g. 127.0.0.1 to only accept a local reverse proxy
+    #[arg(long, value_name = "IP", default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    pub bind: IpAddr,
+
+    /// Port to serve plain HTTP on
+    #[arg(long, default_value_t = 3000)]
+    pub port: u16,
+
+    /// Listen on a Unix domain socket instead of TCP
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, in octal
+    #[arg(long, default_value = "660", value_parser = parse_mode)]
+    pub unix_socket_mode: u32,
+
+    /// Also serve HTTPS on this address, e.g. 0.0.0.0:443
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    pub tls_listen: Option<SocketAddr>,
+
+    /// What the root domain answers over plain HTTP. `redirect` sends its pages to HTTPS,
+    /// on the --tls-listen port when set, and refuses /metrics rather than expose it unencrypted
+    #[arg(long, value_enum, default_value_t = tls::ApexHttp::Serve)]
+    pub apex_http: tls::ApexHttp,
+
+    /// Take `X-Forwarded-Proto: https` on the plain listener as HTTPS, for a proxy in front
+    /// that terminates TLS and sets it. Without it only --tls-listen requests are HTTPS
+    #[arg(long)]
+    pub trust_forwarded_proto: bool,
+
+    /// PEM certificate chain for --tls-listen
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-listen
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve everything below this path prefix, e.g. /sites when a proxy mounts the gateway
+    /// there. The prefix is stripped before routes resolve and added back to redirects and
+    /// generated links, requests outside it get 404
+    #[arg(long, value_parser = base_path::parse)]
+    pub base_path: Option<String>,
+}
+
+fn parse_coming_soon_status(s: &str) -> Result<u16, String> {
+    match s.parse() {
+        Ok(status @ (200 | 503)) => Ok(status),
+        _ => Err(format!("invalid status '{}', expected 200 or 503", s)),
+    }
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|e| format!("invalid octal mode '{}': {}", s, e))
+}
+
+/// Shared state for request handlers
+#[derive(Clone)]
+struct AppState {
+    site_map: SiteMap,
+    site_alias_map: SiteAliasMap,
+    client: Client,
+    /// Clients of the aliases resolved from their own relays
+    relay_pools: relay_pools::RelayPools,
+    site_config: Arc<site::SiteConfig>,
+    args: Arc<Args>,
 }
 
 #[tokio::main]
@@ -43,110 +613,1601 @@ async fn main() -> Result<()> {
         .install_default()
         .map_err(|_| anyhow::anyhow!("Failed to install crypto provider"))?;
 
-    let args = Args::parse();
+    let args = Arc::new(Args::parse());
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &args.otlp_endpoint {
+        otlp::init(endpoint.clone(), env!("CARGO_PKG_NAME"))?;
+    }
     let client = Client::builder().build();
 
-    let relays = if args.relay.is_empty() {
-        vec![
-            "wss://relay.damus.io".to_string(),
-            "wss://relay.snort.social".to_string(),
-            "wss://relay.primal.net".to_string(),
-            "wss://nos.lol".to_string(),
-        ]
-    } else {
-        args.relay
+    let snapshot = match &args.snapshot {
+        Some(dir) => {
+            info!("Serving offline from snapshot {}", dir.display());
+            Some(Arc::new(snapshot::Snapshot::load(dir)?))
+        }
+        None => {
+            let relays = configured_relays(&args)?;
+            for r in &relays {
+                info!("Connecting to {}", r);
+                client.add_relay(r).await?;
+            }
+            client.connect().await;
+            if args.relay_file.is_some() {
+                tokio::spawn(reload_relays_on_hangup(client.clone(), args.clone()));
+            }
+            None
+        }
     };
 
-    for r in &relays {
-        info!("Connecting to {}", r);
-        client.add_relay(r).await?;
-    }
-    client.connect().await;
-
     let site_map = SiteMap::default();
     let site_alias_map = SiteAliasMap::default();
+    if let Some(path) = &args.alias_file {
+        let aliases = alias::load_aliases(path)?;
+        info!("Loaded {} aliases from {}", aliases.len(), path.display());
+        *site_alias_map.write().await = aliases;
+    }
+    if let Some(path) = &args.registry_file {
+        let registry = alias::load_registry(path)?;
+        info!(
+            "Loaded {} registry ids from {}",
+            registry.len(),
+            path.display()
+        );
+        let mut aliases = site_alias_map.write().await;
+        for (id, site) in registry {
+            if aliases.contains_key(&id) {
+                anyhow::bail!("Registry id '{}' is already used by an alias", id);
+            }
+            aliases.insert(id, site);
+        }
+    }
+
+    if args.nip05_provider.len() > nip05::MAX_NIP05_PROVIDERS {
+        anyhow::bail!(
+            "At most {} NIP-05 providers are allowed",
+            nip05::MAX_NIP05_PROVIDERS
+        );
+    }
+
+    let cache_dir = args
+        .cache_dir
+        .clone()
+        .unwrap_or_else(site::default_cache_dir);
+    if snapshot.is_none() {
+        site::prepare_cache_dir(&cache_dir)?;
+        info!("Caching blobs in {}", cache_dir.display());
+    }
+    let disk_lru = match args.cache_max_bytes {
+        Some(max) if snapshot.is_none() => {
+            Some(Arc::new(disk_lru::DiskLru::scan(&cache_dir, max)?))
+        }
+        _ => None,
+    };
+
+    let blob_cache: Arc<dyn blob_cache::BlobCache> = match args.cache_backend {
+        #[cfg(feature = "redis")]
+        blob_cache::CacheBackend::Redis => {
+            let url = args
+                .redis_url
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("--cache-backend redis requires --redis-url"))?;
+            let ttl = (args.redis_ttl > 0).then(|| Duration::from_secs(args.redis_ttl));
+            info!(
+                "Sharing blobs through Redis at {}",
+                url.host_str().unwrap_or_default()
+            );
+            Arc::new(blob_cache::RedisCache::new(url, ttl)?)
+        }
+        blob_cache::CacheBackend::Disk => Arc::new(blob_cache::DiskCache),
+    };
+
+    let mirror_to = match (&args.blob_mirror_to, &args.blob_mirror_key) {
+        (Some(server), Some(key)) => {
+            let keys = nostr_sdk::Keys::parse(key)
+                .map_err(|e| anyhow::anyhow!("Invalid --blob-mirror-key, {}", e))?;
+            info!(
+                "Mirroring discovered blobs to {} as {}",
+                server,
+                keys.public_key()
+            );
+            Some(site::MirrorTarget {
+                server: server.clone(),
+                keys,
+            })
+        }
+        _ => None,
+    };
+
+    let mut route_ttls = route_ttl::RouteTtls::default();
+    for (class, ttl) in &args.route_ttl {
+        route_ttls.set(*class, *ttl);
+    }
+    let site_config = Arc::new(site::SiteConfig {
+        fallback_servers: args.fallback_blossom_server.clone(),
+        mirror_hops: args.blob_mirror_hops as usize,
+        discovery_servers: args.blob_discovery_server.clone(),
+        mirror_to,
+        site_download_concurrency: args.per_site_download_concurrency.map(usize::from),
+        server_list_relay_hints: args.server_list_relay_hints,
+        outbox_relays: args.outbox_relays,
+        spa: args.spa,
+        update_feed: args.update_feed,
+        gone_removed_routes: args.gone_removed_routes,
+        failed_site_ttl: Duration::from_secs(args.failed_site_ttl),
+        route_timeout: Duration::from_secs(args.route_timeout),
+        server_list_timeout: Duration::from_secs(args.server_list_timeout),
+        stream_threshold: args.stream_threshold_bytes,
+        cache_dir,
+        link_duplicates: args.link_duplicate_blobs,
+        disk_lru,
+        blob_cache,
+        content_type_rules: blob_storage::ContentTypeRules {
+            stream: args.stream_content_type.clone(),
+            cache: args.cache_content_type.clone(),
+        },
+        eager_index: args.eager_index,
+        preload_routes: args.preload_routes,
+        publish_grace: Duration::from_secs(args.publish_grace),
+        publish_retry: Duration::from_secs(args.publish_retry),
+        root_site_kind: Kind::Custom(args.root_site_kind),
+        named_site_kind: Kind::Custom(args.named_site_kind),
+        server_list_kind: Kind::Custom(args.server_list_kind),
+        debug_verify: args.debug_verify_log,
+        hex_subdomains: args.hex_subdomains,
+        route_ttls,
+        min_relay_responses: args.min_relay_responses,
+        nip05_providers: args.nip05_provider.clone(),
+        nip05_timeout: Duration::from_secs(args.nip05_timeout),
+        nip05_ttl: Duration::from_secs(args.nip05_ttl),
+        nip05_limits: nip05::FetchLimits {
+            max_bytes: args.nip05_max_bytes,
+            allow_private: args.nip05_allow_private,
+        },
+        server_ordering: args.server_ordering,
+        server_breaker: args
+            .server_breaker_failures
+            .map(|failures| server_health::Breaker {
+                failures: failures as usize,
+                window: Duration::from_secs(args.server_breaker_window),
+                cooldown: Duration::from_secs(args.server_breaker_cooldown),
+            }),
+        snapshot,
+        ..Default::default()
+    });
+
+    if let Some(dir) = &args.export_snapshot {
+        // Exported blobs must all land in the cache, however large
+        let config = Arc::new(site::SiteConfig {
+            stream_threshold: None,
+            content_type_rules: Default::default(),
+            disk_lru: None,
+            ..(*site_config).clone()
+        });
+        snapshot::export(&client, &config, &args.export_site, dir).await?;
+        info!("Wrote snapshot to {}", dir.display());
+        return Ok(());
+    }
 
+    if let Some(budget) = args.max_memory_bytes {
+        tokio::spawn(memory::enforce_budget(site_map.clone(), budget));
+    }
+    if args.reconnecting_page && site_config.snapshot.is_none() {
+        tokio::spawn(relays::watch_connectivity(client.clone()));
+    }
+    if args.live_updates && site_config.snapshot.is_none() {
+        tokio::spawn(live::follow_sites(
+            client.clone(),
+            site_map.clone(),
+            site_config.clone(),
+        ));
+    }
+
+    let admin = args.metrics_listen.map(|addr| {
+        let site_map = site_map.clone();
+        let app = Router::new().route(
+            "/metrics",
+            get(move || async move { metrics_response(&site_map).await }),
+        );
+        (addr, app)
+    });
+    let state = AppState {
+        site_map,
+        site_alias_map,
+        client,
+        relay_pools: Default::default(),
+        site_config,
+        args: args.clone(),
+    };
     let app = Router::new()
         .route("/", get(serve_site))
         .route("/{*path}", get(serve_site))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            nsite_endpoints,
+        ))
+        .layer(tower::util::option_layer(
+            args.site_versions
+                .then(|| axum::middleware::from_fn(site_versions)),
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            resolve_site,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            noindex_aliases,
+        ))
+        .layer(axum::middleware::from_fn(methods::restrict_requests))
+        .layer(compression::compression_layer(
+            args.compress,
+            args.compress_min_bytes,
+            &args.compress_encoding,
+        ))
+        .layer(tower::util::option_layer(
+            args.compress
+                .then(|| axum::middleware::from_fn(etag::weaken)),
+        ))
+        .layer(tower::util::option_layer(args.gateway.then(|| {
+            axum::middleware::from_fn_with_state(args.base_path.clone(), gateway::route_by_path)
+        })))
         .layer(CorsLayer::permissive())
-        .layer(tower_http::trace::TraceLayer::new_for_http())
-        .with_state((site_map, site_alias_map, client));
+        .layer(axum::middleware::from_fn_with_state(
+            args.base_path.clone(),
+            base_path::strip_base_path,
+        ))
+        .layer(axum::middleware::from_fn(methods::server_options))
+        .layer(tower::util::option_layer(request_tracing(&args).map(
+            |trust_traceparent| {
+                axum::middleware::from_fn_with_state(trust_traceparent, trace_requests)
+            },
+        )))
+        .layer(tower_http::trace::TraceLayer::new_for_http());
+    let app = if args.access_log {
+        app.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            log_requests,
+        ))
+    } else {
+        app
+    }
+    .layer(tower::util::option_layer(args.trust_forwarded_proto.then(
+        || axum::middleware::from_fn(tls::trust_forwarded_proto),
+    )))
+    .layer(tower::util::option_layer(
+        (args.trailing_dot == host::TrailingDot::Strip)
+            .then(|| axum::middleware::from_fn(host::strip_trailing_dot)),
+    ))
+    .with_state(state);
 
-    let addr = SocketAddr::from((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 3000));
-    info!("Listening on {}", addr);
+    // HTTPS runs next to the plain listener, sharing the same app and shutdown signal
+    let https = async {
+        let (Some(addr), Some(cert), Some(key)) = (args.tls_listen, &args.tls_cert, &args.tls_key)
+        else {
+            return Ok(());
+        };
+        let config = tls::load_config(cert, key)?;
+        let listener = TcpListener::bind(addr).await?;
+        info!("Listening on {} (TLS)", addr);
+        tls::serve(listener, config, app.clone(), shutdown_signal()).await
+    };
+    // Metrics get a listener of their own, for scrapers on a private network
+    let metrics = async {
+        let Some((addr, app)) = admin else {
+            return Ok(());
+        };
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+        info!("Serving metrics on {}", addr);
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let http = serve_http(&args, app.clone());
+    tokio::try_join!(http, https, metrics)?;
+    #[cfg(feature = "otlp")]
+    tokio::task::spawn_blocking(otlp::shutdown).await?;
 
-    let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Serve plain HTTP on the Unix socket or TCP port until shutdown
+async fn serve_http(args: &Args, app: Router) -> Result<()> {
+    if let Some(path) = &args.unix_socket {
+        // Remove a stale socket left behind by an unclean shutdown, but never anything else
+        if let Ok(meta) = std::fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                anyhow::bail!("{} exists and is not a socket", path.display());
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, Permissions::from_mode(args.unix_socket_mode))?;
+        info!("Listening on {}", path.display());
+
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await;
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove socket {}: {}", path.display(), e);
+        }
+        result?;
+    } else {
+        let addr = SocketAddr::from((args.bind, args.port));
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+        info!("Listening on {}", addr);
+
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    }
 
     Ok(())
 }
 
+/// Relays from the command line and relay file, or the default relays if neither lists any
+fn configured_relays(args: &Args) -> Result<Vec<RelayUrl>> {
+    let mut relays = args
+        .relay
+        .iter()
+        .map(|r| RelayUrl::parse(r).map_err(|e| anyhow::anyhow!("invalid relay '{}': {}", r, e)))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(path) = &args.relay_file {
+        for r in relays::load_relay_file(path)? {
+            if !relays.contains(&r) {
+                relays.push(r);
+            }
+        }
+    }
+    if relays.is_empty() {
+        relays = DEFAULT_RELAYS
+            .iter()
+            .map(|r| RelayUrl::parse(r).unwrap())
+            .collect();
+    }
+    Ok(relays)
+}
+
+/// Reconcile the relay pool with the relay file every time SIGHUP is received
+async fn reload_relays_on_hangup(client: Client, args: Arc<Args>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading relays");
+        let result = match configured_relays(&args) {
+            Ok(wanted) => relays::reconcile(&client, &wanted).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((added, removed)) => {
+                for r in &added {
+                    info!("Added relay {}", r);
+                }
+                for r in &removed {
+                    info!("Removed relay {}", r);
+                }
+                info!(
+                    "Relays reloaded, {} added, {} removed",
+                    added.len(),
+                    removed.len()
+                );
+            }
+            Err(e) => error!("Failed to reload relays: {}", e),
+        }
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to install SIGTERM handler: {}", e);
+            let _ = ctrl_c.await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate.recv() => {},
+    }
+    info!("Shutting down");
+}
+
+/// Get a single query parameter value from a request
+fn query_param<'a>(request: &'a axum::extract::Request, name: &str) -> Option<&'a str> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Decode a percent-encoded query parameter value
+fn percent_decode(value: &str) -> String {
+    percent_encoding::percent_decode_str(value)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// JSON identity document, readable cross-origin as NIP-05 requires
+fn identity_response(doc: serde_json::Value, content_type: &'static str) -> Response {
+    let mut response = Response::new(Body::from(doc.to_string()));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+/// Current metrics in the Prometheus text format
+async fn metrics_response(site_map: &SiteMap) -> Response {
+    metrics::METRICS.set_sites_cached(site_map.read().await.len());
+    let body = metrics::METRICS.render() + &server_health::SERVER_HEALTH.render();
+    let mut response = Response::new(Body::from(body));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        "text/plain; version=0.0.4".parse().unwrap(),
+    );
+    response
+}
+
+/// Mark a site response with the site's version, and as depending on the version a client
+/// asks for
+fn set_site_version(response: &mut Response, version: Option<u64>) {
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static(SITE_MIN_VERSION));
+    if let Some(version) = version {
+        headers.insert(SITE_VERSION, HeaderValue::from(version));
+    }
+}
+
+/// `409 Conflict` for a client expecting a newer version of the site than is published
+fn stale_version_response(version: Option<u64>) -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::CONFLICT;
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    set_site_version(&mut response, version);
+    response
+}
+
+/// Optional features and whether they're enabled, for the capabilities document
+fn enabled_features(args: &Args) -> Vec<(&'static str, bool)> {
+    vec![
+        ("spa", args.spa),
+        ("compression", args.compress),
+        ("cache_control", args.cache_control),
+        ("canonical_index", args.canonical_index.is_some()),
+        ("image_resize", args.image_resize),
+        ("identicon_favicon", args.identicon_favicon),
+        ("sitemap", args.generate_sitemap),
+        ("site_headers", args.site_headers),
+        ("site_versions", args.site_versions),
+        ("site_api", args.site_api),
+        ("subresource_integrity", args.inject_sri),
+        ("preload_links", args.preload_links),
+        ("prefetch", args.prefetch),
+        ("live_updates", args.live_updates),
+        ("update_feed", args.update_feed),
+        ("gone_removed_routes", args.gone_removed_routes),
+        ("nip05", args.nip05),
+        ("outbox_relays", args.outbox_relays),
+        ("metrics", args.metrics),
+    ]
+}
+
+/// Scheme the client used, https only for the TLS listener or a trusted proxy
+fn request_scheme(request: &axum::extract::Request) -> &str {
+    if tls::is_https(request) {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+/// Set the content type of a response from the file extension
+fn set_content_type(response: &mut Response, file_path: &Path) {
+    let content_type = extension_content_type(file_path).unwrap_or("application/octet-stream");
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+}
+
+/// Set the content type of a response serving a cached file
+///
+/// Files without a known extension, like hashed asset names, are sniffed from their first
+/// bytes before falling back to `application/octet-stream`.
+async fn set_file_content_type(response: &mut Response, file_path: &Path) {
+    let content_type = match extension_content_type(file_path) {
+        Some(content_type) => content_type,
+        None => sniffed_content_type(file_path)
+            .await
+            .unwrap_or("application/octet-stream"),
+    };
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+}
+
+async fn sniffed_content_type(file_path: &Path) -> Option<&'static str> {
+    let mut head = vec![0u8; sniff::SNIFF_LEN];
+    let mut file = File::open(file_path).await.ok()?;
+    let mut len = 0;
+    while len < head.len() {
+        match file.read(&mut head[len..]).await.ok()? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    sniff::content_type(&head[..len])
+}
+
+fn set_cache_control(response: &mut Response, policy: Option<String>) {
+    if let Some(policy) = policy.and_then(|p| HeaderValue::from_str(&p).ok()) {
+        response.headers_mut().insert(header::CACHE_CONTROL, policy);
+    }
+}
+
+/// Set the `ETag` and the `Last-Modified` time of the manifest that published the file
+fn set_validators(
+    response: &mut Response,
+    etag: Option<HeaderValue>,
+    created_at: Option<Timestamp>,
+) {
+    if let Some(etag) = etag {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    if let Some(created_at) = created_at {
+        response.headers_mut().insert(
+            header::LAST_MODIFIED,
+            cache_control::last_modified(created_at),
+        );
+    }
+}
+
+/// A site's own not-found page, sent with a 404 status
+async fn not_found_page(
+    content: site::RouteContent,
+    stream_threshold: Option<u64>,
+) -> Result<Response, StatusCode> {
+    let mut response = match content {
+        site::RouteContent::Cached(file_path) => {
+            let body = file_body(&file_path, stream_threshold).await.map_err(|e| {
+                error!("Failed to read file {}: {}", file_path.display(), e);
+                StatusCode::NOT_FOUND
+            })?;
+            let mut response = Response::new(body);
+            set_file_content_type(&mut response, &file_path).await;
+            response
+        }
+        site::RouteContent::Stream { path, body, .. } => {
+            let mut response = Response::new(Body::from_stream(body));
+            set_content_type(&mut response, &path);
+            response
+        }
+    };
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    Ok(response)
+}
+
+/// Read a cached file into a response body
+/// Files over the stream threshold are streamed from disk instead of buffered in memory
+async fn file_body(file_path: &Path, stream_threshold: Option<u64>) -> std::io::Result<Body> {
+    let mut file = File::open(file_path).await?;
+    let len = file.metadata().await?.len();
+    if stream_threshold.is_some_and(|t| len > t) {
+        return Ok(Body::from_stream(ReaderStream::new(file)));
+    }
+
+    let mut contents = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut contents).await?;
+    Ok(Body::from(contents))
+}
+
+/// `Link: rel="canonical"` header pointing a served variant at the resource at `path`
+fn canonical_link(request: &axum::extract::Request, host: &str, path: &str) -> Option<HeaderValue> {
+    let link = format!(
+        "<{}://{}{}>; rel=\"canonical\"",
+        request_scheme(request),
+        host,
+        path
+    );
+    HeaderValue::from_str(&link).ok()
+}
+
+/// Preload `Link` header for the assets referenced by a cached page
+async fn page_preload_links(page: &Path, page_path: &str, base: &str) -> Option<HeaderValue> {
+    let html = tokio::fs::read(page).await.ok()?;
+    let paths: Vec<String> = prefetch::asset_paths(&html, page_path)
+        .inspect_err(|e| warn!("Failed to read assets of {}: {}", page_path, e))
+        .ok()?
+        .into_iter()
+        .map(|p| format!("{}{}", base, p))
+        .collect();
+    let links = prefetch::preload_links(&paths, PRELOAD_LINKS_LIMIT)?;
+    HeaderValue::from_str(&links).ok()
+}
+
+fn is_html(file_path: &Path) -> bool {
+    matches!(
+        file_path.extension().and_then(|e| e.to_str()),
+        Some("html") | Some("htm")
+    )
+}
+
+/// Origins whose absolute links should be rewritten for the alias a request came in on
+async fn rewrite_origins(host: &str, alias_map: &SiteAliasMap) -> Vec<String> {
+    let Some(subdomain) = site::subdomain(host) else {
+        return Vec::new();
+    };
+    alias::lookup(&*alias_map.read().await, subdomain)
+        .map(|a| a.rewrite_origins.clone())
+        .unwrap_or_default()
+}
+
+/// JSON listing of the files a site publishes under `prefix`
+async fn list_response(
+    site: &site::SiteInfo,
+    config: &site::SiteConfig,
+    prefix: &str,
+) -> Result<Response, StatusCode> {
+    let routes = match site.list(prefix).await {
+        Ok(Some(routes)) => routes,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to list {}: {}", prefix, e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    let mut files = Vec::with_capacity(routes.len());
+    for route in routes {
+        // Sizes are only known for blobs already in the cache
+        let size = tokio::fs::metadata(route.cache_path(config))
+            .await
+            .ok()
+            .map(|m| m.len());
+        files.push(serde_json::json!({
+            "path": route.path,
+            "sha256": hex::encode(route.key),
+            "size": size,
+        }));
+    }
+    let doc = serde_json::json!({ "prefix": prefix, "files": files });
+    let mut response = Response::new(Body::from(doc.to_string()));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Ok(compression::generated(response))
+}
+
+/// JSON description of the route resolved for `path`, with the servers holding its blob
+async fn route_response(
+    site: &site::SiteInfo,
+    config: &site::SiteConfig,
+    path: &str,
+) -> Result<Response, StatusCode> {
+    let (route, servers) = match site.resolve(path).await {
+        Ok(Some(resolved)) => resolved,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to resolve {}: {}", path, e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    let available = route.probe(&servers).await;
+    let servers: Vec<_> = servers
+        .iter()
+        .zip(available)
+        .map(|(url, available)| serde_json::json!({ "url": url, "available": available }))
+        .collect();
+    let cached = config
+        .blob_cache
+        .contains(&route.key, &route.cache_path(config))
+        .await
+        .unwrap_or(false);
+    let doc = serde_json::json!({
+        "path": route.path,
+        "sha256": hex::encode(route.key),
+        "created_at": route.created_at.as_secs(),
+        "cached": cached,
+        "servers": servers,
+    });
+    let mut response = Response::new(Body::from(doc.to_string()));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Ok(compression::generated(response))
+}
+
+/// Redirect response to `location`
+fn redirect(status: StatusCode, location: &str) -> Result<Response, StatusCode> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::LOCATION,
+        location.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+    );
+    Ok(response)
+}
+
+/// Compare a configured host with a Host header, ignoring the port unless one is configured
+fn is_same_host(configured: &str, host: &str) -> bool {
+    if configured.contains(':') {
+        configured.eq_ignore_ascii_case(host)
+    } else {
+        configured.eq_ignore_ascii_case(host.split(':').next().unwrap_or(host))
+    }
+}
+
+/// Whether requests are traced, with whether their `traceparent` is trusted
+fn request_tracing(args: &Args) -> Option<bool> {
+    #[cfg(feature = "otlp")]
+    {
+        args.otlp_endpoint
+            .as_ref()
+            .map(|_| args.otlp_trust_traceparent)
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = args;
+        None
+    }
+}
+
+/// Run every request in a server span, the root of its trace unless `trust_traceparent`
+/// is set and the request carries a `traceparent` to continue
+async fn trace_requests(
+    State(trust_traceparent): State<bool>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let mut span = otlp::Span::server("request", request.headers(), trust_traceparent)
+        .attr("http.request.method", request.method())
+        .attr("server.address", host)
+        .attr("url.path", request.uri().path());
+    let response = span.scope(next.run(request)).await;
+    span.set_attr("http.response.status_code", response.status().as_u16());
+    if response.status().is_server_error() {
+        span.fail(response.status());
+    }
+    response
+}
+
+/// Write one access log line per request, with as much detail as the site's alias allows
+async fn log_requests(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let start = std::time::Instant::now();
+    let client = request
+        .extensions()
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .map(|c| c.0.ip())
+        .or_else(|| {
+            // Unix socket clients are reverse proxies passing the visitor on
+            request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        });
+    let host = host::request_host(&request, state.args.host_conflict)
+        .unwrap_or("-")
+        .to_string();
+    let mode = match site::subdomain(host::site_host(&host, state.args.www)) {
+        Some(subdomain) => alias::lookup(&*state.site_alias_map.read().await, subdomain)
+            .map(|a| a.access_log)
+            .unwrap_or_default(),
+        None => Default::default(),
+    };
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let response = next.run(request).await;
+    let visit = access_log::Visit {
+        client,
+        host: &host,
+        method: &method,
+        path: &path,
+        status: response.status(),
+        elapsed: start.elapsed(),
+    };
+    if let Some(line) = access_log::line(mode, &visit) {
+        info!(target: "access", "{}", line);
+    }
+    response
+}
+
+/// Keep search engines out of sites served on `noindex` aliases
+///
+/// Every response gets `X-Robots-Tag: noindex` and `/robots.txt` disallows everything,
+/// whatever the site publishes itself.
+async fn noindex_aliases(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let noindex = match host::request_host(&request, state.args.host_conflict)
+        .ok()
+        .and_then(|host| site::subdomain(host::site_host(host, state.args.www)))
+    {
+        Some(subdomain) => {
+            alias::lookup(&*state.site_alias_map.read().await, subdomain).is_some_and(|a| a.noindex)
+        }
+        None => false,
+    };
+    if !noindex {
+        return next.run(request).await;
+    }
+    let mut response = if request.uri().path() == "/robots.txt" {
+        let mut response = Response::new(Body::from(NOINDEX_ROBOTS_TXT));
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        response
+    } else {
+        next.run(request).await
+    };
+    response
+        .headers_mut()
+        .insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    response
+}
+
+/// Whether the client asked for a fresh answer with `Cache-Control: no-cache`
+fn is_no_cache(request: &axum::extract::Request) -> bool {
+    request
+        .headers()
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|d| d.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// Whether a site failed to load because too few relays answered
+fn is_relay_quorum_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<relays::RelayQuorumError>().is_some()
+}
+
+/// Whether the request came in on a reserved alias
+async fn is_reserved(host: &str, alias_map: &SiteAliasMap) -> bool {
+    let Some(subdomain) = site::subdomain(host) else {
+        return false;
+    };
+    alias::lookup(&*alias_map.read().await, subdomain).is_some_and(|a| a.reserved)
+}
+
+/// The gateway's security.txt from --security-txt
+async fn security_txt_response(args: &Args) -> Result<Response, StatusCode> {
+    let Some(path) = &args.security_txt else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let body = tokio::fs::read(path).await.map_err(|e| {
+        error!("Failed to read security.txt {}: {}", path.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut response = Response::new(Body::from(body));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    Ok(response)
+}
+
+/// Coming-soon page for a reserved alias, from --coming-soon-file or the built-in page
+async fn coming_soon_response(args: &Args) -> Result<Response, StatusCode> {
+    let body = match &args.coming_soon_file {
+        Some(path) => Body::from(tokio::fs::read(path).await.map_err(|e| {
+            error!("Failed to read coming-soon page {}: {}", path.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?),
+        None => Body::from(COMING_SOON_HTML),
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() =
+        StatusCode::from_u16(args.coming_soon_status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("3600"));
+    }
+    Ok(response)
+}
+
+/// Page for sites that can't be resolved while every relay is disconnected
+async fn reconnecting_response(args: &Args) -> Result<Response, StatusCode> {
+    let body = match &args.reconnecting_file {
+        Some(path) => Body::from(tokio::fs::read(path).await.map_err(|e| {
+            error!("Failed to read reconnecting page {}: {}", path.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?),
+        None => Body::from(RECONNECTING_HTML),
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(
+        header::RETRY_AFTER,
+        HeaderValue::from_static(RECONNECTING_RETRY_AFTER),
+    );
+    Ok(response)
+}
+
+/// Canonical host configured by an alias of `site`, if any
+async fn canonical_host(site: &site::SiteInfo, alias_map: &SiteAliasMap) -> Option<String> {
+    let aliases = alias_map.read().await;
+    if aliases.values().all(|a| a.canonical_host.is_none()) {
+        return None;
+    }
+    let key = site.cache_key().await;
+    aliases.values().find_map(|a| {
+        let host = a.canonical_host.as_ref()?;
+        (site::site_cache_key(&a.pubkey, a.identifier.as_deref(), a.pin.as_ref()) == key)
+            .then(|| host.clone())
+    })
+}
+
+/// Read a cached HTML file, rewriting absolute links to `origins` and adding the integrity
+/// of the assets in `hashes`
+async fn rewritten_html(
+    file_path: &Path,
+    origins: &[String],
+    page_path: &str,
+    hashes: Option<&HashMap<String, [u8; 32]>>,
+) -> std::io::Result<Vec<u8>> {
+    let mut html = tokio::fs::read(file_path).await?;
+    if !origins.is_empty() {
+        html = rewrite::rewrite_links(&html, origins).map_err(std::io::Error::other)?;
+    }
+    if let Some(hashes) = hashes {
+        html = sri::inject_integrity(&html, page_path, hashes).map_err(std::io::Error::other)?;
+    }
+    Ok(html)
+}
+
+/// Empty `304 Not Modified` response, for requests whose `If-None-Match` is current
+fn not_modified() -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    response
+}
+
+/// Site a request is for, worked out once by [`resolve_site`] for the handlers after it
+#[derive(Clone)]
+struct SiteRequest {
+    /// Host the client asked for
+    host: String,
+    /// Host the site is looked up by, without `www.` or from the path in path mode
+    site_host: String,
+    /// Path the site is served below, the gateway's base path and any path-mode segment
+    base: String,
+    /// Requested path, normalized and without its leading `/`
+    path: String,
+    site: Result<Option<site::SiteInfo>, Arc<anyhow::Error>>,
+}
+
+/// Resolve the site of a request's host, redirecting to the host and path it's
+/// canonically served on
+async fn resolve_site(
+    State(state): State<AppState>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response, StatusCode> {
+    let args = &state.args;
+    methods::check_path_length(request.uri(), args.max_path_length)?;
+    let Some(clean_path) = site::route_path(request.uri()) else {
+        info!("Refusing request for {}", request.uri().path());
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    let host = host::request_host(&request, args.host_conflict)?.to_string();
+    let gateway_base = args.base_path.as_deref().unwrap_or("");
+    // In path mode sites come from the path alone, and live below their segment
+    let path_site = request.extensions().get::<gateway::PathSite>();
+    let (site_host, base) = match path_site {
+        Some(site) => (site.host(), format!("{}/{}", gateway_base, site.0)),
+        None if args.gateway => (gateway::ROOT_HOST.to_string(), gateway_base.to_string()),
+        None => (
+            host::site_host(&host, args.www).to_string(),
+            gateway_base.to_string(),
+        ),
+    };
+    if args.www == Some(host::WwwPrefix::Redirect)
+        && let Some(bare) = host::without_www(&host)
+    {
+        let path_and_query = request.uri().path_and_query().map_or("/", |p| p.as_str());
+        let location = format!(
+            "{}://{}{}{}",
+            request_scheme(&request),
+            bare,
+            base,
+            path_and_query
+        );
+        return redirect(StatusCode::MOVED_PERMANENTLY, &location);
+    }
+    if is_no_cache(&request) {
+        site::forget_failed_load(&site_host);
+    }
+
+    let site = site::SiteInfo::from_request(
+        &site_host,
+        &state.client,
+        &state.site_config,
+        &state.site_map,
+        &state.site_alias_map,
+        &state.relay_pools,
+    )
+    .await;
+    if let Ok(Some(site)) = &site {
+        if let Some(canonical) = canonical_host(site, &state.site_alias_map).await
+            && !is_same_host(&canonical, &host)
+        {
+            let path_and_query = request.uri().path_and_query().map_or("/", |p| p.as_str());
+            let location = format!(
+                "{}://{}{}{}",
+                request_scheme(&request),
+                canonical,
+                gateway_base,
+                path_and_query
+            );
+            return redirect(StatusCode::MOVED_PERMANENTLY, &location);
+        }
+
+        if let Some(canonical) = args.canonical_index
+            && let Some(target) = canonical_index::redirect_target(request.uri().path(), canonical)
+        {
+            let location = match request.uri().query() {
+                Some(query) => format!("{}{}?{}", base, target, query),
+                None => format!("{}{}", base, target),
+            };
+            return redirect(StatusCode::MOVED_PERMANENTLY, &location);
+        }
+    }
+
+    request.extensions_mut().insert(SiteRequest {
+        host,
+        site_host,
+        base,
+        path: clean_path.trim_start_matches('/').to_string(),
+        site: site.map_err(Arc::new),
+    });
+    Ok(next.run(request).await)
+}
+
+/// Tag every response of a site with its version, refusing clients that expect a newer
+/// one, for `--site-versions`
+async fn site_versions(
+    axum::Extension(target): axum::Extension<SiteRequest>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response, StatusCode> {
+    let Ok(Some(site)) = &target.site else {
+        return Ok(next.run(request).await);
+    };
+    let version = site.version().await;
+    if let Some(min) = request.headers().get(SITE_MIN_VERSION) {
+        let min = min
+            .to_str()
+            .ok()
+            .and_then(|m| m.parse().ok())
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if site_version::is_older(version, min) {
+            info!(
+                "Site version {:?} is older than {} the client asked for",
+                version, min
+            );
+            return Ok(stale_version_response(version));
+        }
+    }
+    let mut response = next.run(request).await;
+    set_site_version(&mut response, version);
+    Ok(response)
+}
+
+/// Answer the gateway's own `_nsite/*` endpoints, on a site's host or, for
+/// `_nsite/tls-ask`, on one that isn't a site
+async fn nsite_endpoints(
+    State(state): State<AppState>,
+    axum::Extension(target): axum::Extension<SiteRequest>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response, StatusCode> {
+    let args = &state.args;
+    match (&target.site, target.path.as_str()) {
+        (Ok(Some(site)), "_nsite/route") if args.site_api => {
+            let path = query_param(&request, "path")
+                .map(percent_decode)
+                .filter(|p| p.starts_with('/'))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            route_response(site, &state.site_config, &path).await
+        }
+        (Ok(Some(site)), "_nsite/feed.json") if args.update_feed => {
+            let home = format!(
+                "{}://{}{}/",
+                request_scheme(&request),
+                target.host,
+                target.base
+            );
+            let feed = feed::json_feed(
+                &site.cache_key().await,
+                &home,
+                &format!("{}_nsite/feed.json", home),
+            );
+            let mut response = Response::new(Body::from(feed.to_string()));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/feed+json"),
+            );
+            Ok(compression::generated(response))
+        }
+        (Ok(Some(site)), "_nsite/ls") if args.site_api => {
+            let prefix = query_param(&request, "prefix").map(percent_decode);
+            list_response(site, &state.site_config, prefix.as_deref().unwrap_or("/")).await
+        }
+        // Caddy asks about certificates over plain HTTP, so this comes before --apex-http
+        (Ok(None), "_nsite/tls-ask") if !args.tls_ask_domain.is_empty() => {
+            let hostname = query_param(&request, "domain")
+                .ok_or(StatusCode::BAD_REQUEST)?
+                .to_ascii_lowercase();
+            tls_ask_response(&state, hostname).await
+        }
+        _ => Ok(next.run(request).await),
+    }
+}
+
+/// Approve a certificate for the `domain` Caddy asks about when it's a site's hostname
+async fn tls_ask_response(state: &AppState, hostname: String) -> Result<Response, StatusCode> {
+    let args = &state.args;
+    if !tls_ask::is_allowed_hostname(&hostname, &args.tls_ask_domain) {
+        info!(
+            "Refusing certificate for {}, not under an allowed domain",
+            hostname
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // The limits are checked before resolving, which queries relays
+    let admission = tls_ask::TLS_APPROVALS.lock().unwrap().admit(
+        &hostname,
+        args.tls_ask_max_per_hour,
+        std::time::Instant::now(),
+    );
+    match admission {
+        tls_ask::Admission::Approved => return Ok(Response::new(Body::from("ok"))),
+        tls_ask::Admission::Limited => {
+            warn!(
+                "Refusing certificate for {}, hourly limit reached",
+                hostname
+            );
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        tls_ask::Admission::Lookup => {}
+    }
+    // Only hostnames that resolve to a site get a certificate
+    let site_hostname = host::site_host(&hostname, args.www);
+    match site::SiteInfo::from_request(
+        site_hostname,
+        &state.client,
+        &state.site_config,
+        &state.site_map,
+        &state.site_alias_map,
+        &state.relay_pools,
+    )
+    .await
+    {
+        Ok(Some(_)) => {}
+        _ => {
+            info!("Refusing certificate for {}, no site found", hostname);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+    let approved = tls_ask::TLS_APPROVALS.lock().unwrap().approve(
+        &hostname,
+        args.tls_ask_max_per_hour,
+        std::time::Instant::now(),
+    );
+    if !approved {
+        warn!(
+            "Refusing certificate for {}, hourly limit reached",
+            hostname
+        );
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    Ok(Response::new(Body::from("ok")))
+}
+
+/// Serve a route of the request's site, or the gateway's own pages on a host that isn't one
 async fn serve_site(
-    State((site_map, site_alias_map, client)): State<(SiteMap, SiteAliasMap, Client)>,
+    State(state): State<AppState>,
+    axum::Extension(target): axum::Extension<SiteRequest>,
     request: axum::extract::Request,
 ) -> Result<Response, StatusCode> {
-    let path_str = request.uri().path().trim_start_matches('/');
+    let AppState {
+        site_map,
+        site_alias_map,
+        client,
+        site_config,
+        args,
+        ..
+    } = state;
+    let SiteRequest {
+        host,
+        site_host,
+        base,
+        path,
+        site,
+    } = target;
+    let (host, base, path_str) = (host.as_str(), base.as_str(), path.as_str());
     let path_buf = if path_str.is_empty() {
         "index.html".to_string()
     } else {
         path_str.to_string()
     };
 
-    let host = request
-        .headers()
-        .get(header::HOST)
-        .and_then(|h| h.to_str().ok())
-        .ok_or(StatusCode::BAD_REQUEST)?;
-
-    match site::SiteInfo::from_request(host, &client, &site_map, &site_alias_map).await {
+    match site {
         Ok(Some(site)) => {
-            match site.serve_route(&format!("/{}", path_buf)).await {
-                Ok(file_path) => {
-                    let mut file = File::open(&file_path).await.map_err(|_| {
-                        error!("Failed to open file");
-                        StatusCode::INTERNAL_SERVER_ERROR
-                    })?;
+            let mut route_path = format!("/{}", path_buf);
+            // Set when a variant of the requested resource is served instead of itself
+            let mut canonical_path: Option<String> = None;
+            if let Some(name) = &args.geo_header
+                && let Some(country) = request
+                    .headers()
+                    .get(name.as_str())
+                    .and_then(|h| h.to_str().ok())
+            {
+                match site.geo_rule(country).await {
+                    Some(geo::GeoRule::Block) => {
+                        info!("Blocking {} for country {}", route_path, country);
+                        return Err(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+                    }
+                    Some(geo::GeoRule::Redirect(target)) if target != request.uri().path() => {
+                        return redirect(StatusCode::FOUND, &format!("{}{}", base, target));
+                    }
+                    Some(geo::GeoRule::Index(path)) if path_buf == "index.html" => {
+                        canonical_path = Some(path.clone());
+                        route_path = path;
+                    }
+                    _ => {}
+                }
+            }
+
+            let max_ages = cache_control::MaxAges {
+                html: args.html_max_age,
+                immutable: args.immutable_max_age,
+                other: args.asset_max_age,
+            };
+            let site_max_age = match args.site_max_age_cap {
+                Some(cap) => site.cache_max_age(&route_path).await.map(|age| (age, cap)),
+                None => None,
+            };
+            let cache_policy = args.cache_control.then(|| match site_max_age {
+                Some((age, cap)) => cache_control::site_policy(age, cap),
+                None => cache_control::policy(&route_path, &max_ages),
+            });
+            let content = site.serve_route(&route_path).await;
+            let key = content.as_ref().ok().and_then(|c| c.key());
+            let created_at = site.last_modified().await;
+            let request_headers = request.headers();
+            let is_fresh = |etag: Option<&HeaderValue>| {
+                etag.is_some_and(|e| etag::is_fresh(request_headers, e))
+                    || created_at
+                        .is_some_and(|t| cache_control::is_unmodified_since(request_headers, t))
+            };
+            match content {
+                Ok(site::RouteContent::Stream { path, body, .. }) => {
+                    let etag = key.map(|k| etag::etag(&k, None));
+                    let mut response = if is_fresh(etag.as_ref()) {
+                        not_modified()
+                    } else {
+                        Response::new(Body::from_stream(body))
+                    };
+                    set_content_type(&mut response, &path);
+                    set_cache_control(&mut response, cache_policy);
+                    set_validators(&mut response, etag, created_at);
+                    if let Some(link) = canonical_path
+                        .and_then(|p| canonical_link(&request, host, &format!("{}{}", base, p)))
+                    {
+                        response.headers_mut().append(header::LINK, link);
+                    }
+                    if args.site_headers {
+                        response.headers_mut().extend(site.headers().await);
+                    }
+                    Ok(response)
+                }
+                Ok(site::RouteContent::Cached(mut file_path)) => {
+                    let mut variant = None;
+                    if args.image_resize
+                        && let Some(width) = query_param(&request, "w")
+                    {
+                        let width = match width.parse::<u32>() {
+                            Ok(w) if w > 0 && w <= args.image_max_width => w,
+                            _ => return Err(StatusCode::BAD_REQUEST),
+                        };
+                        match resize::resized(&file_path, width).await {
+                            Ok(Some(p)) => {
+                                file_path = p;
+                                variant = Some(format!("w{}", width));
+                                canonical_path = Some(request.uri().path().to_string());
+                            }
+                            Ok(None) => {}
+                            Err(e) => warn!("Failed to resize {}: {}", file_path.display(), e),
+                        }
+                    }
+
+                    let preload = if args.preload_links && is_html(&file_path) {
+                        page_preload_links(&file_path, &route_path, base).await
+                    } else {
+                        None
+                    };
 
-                    let mut contents = Vec::new();
-                    file.read_to_end(&mut contents).await.map_err(|_| {
-                        error!("Failed to read file");
+                    if args.prefetch_links && is_html(&file_path) {
+                        let site = site.clone();
+                        let page = file_path.clone();
+                        let page_path = route_path.clone();
+                        let depth = args.prefetch_link_depth.into();
+                        let limit = args.max_routes_per_request;
+                        tokio::spawn(async move {
+                            site.prefetch_links(&page, &page_path, depth, limit).await
+                        });
+                    }
+
+                    if args.prefetch && is_html(&file_path) {
+                        let site = site.clone();
+                        let page = file_path.clone();
+                        let page_path = route_path.clone();
+                        let limit = args.max_routes_per_request.saturating_sub(1);
+                        tokio::spawn(async move { site.prefetch(&page, &page_path, limit).await });
+                    }
+
+                    let origins = rewrite_origins(&site_host, &site_alias_map).await;
+                    let hashes = if args.inject_sri && is_html(&file_path) {
+                        site.route_hashes().await
+                    } else {
+                        None
+                    };
+                    // Rewritten pages are tagged by what they became, files by their blob
+                    let blob_etag = key.map(|k| etag::etag(&k, variant.as_deref()));
+                    let rewrite = (!origins.is_empty() || hashes.is_some()) && is_html(&file_path);
+                    let (len, byte_range) = if rewrite || is_fresh(blob_etag.as_ref()) {
+                        (0, range::ByteRange::Full)
+                    } else {
+                        match tokio::fs::metadata(&file_path).await {
+                            Ok(meta) => (
+                                meta.len(),
+                                range::requested(
+                                    request_headers,
+                                    meta.len(),
+                                    blob_etag.as_ref(),
+                                    created_at,
+                                ),
+                            ),
+                            Err(_) => (0, range::ByteRange::Full),
+                        }
+                    };
+                    if byte_range == range::ByteRange::Unsatisfiable {
+                        return Ok(range::unsatisfiable(len));
+                    }
+                    let (body, etag) = if rewrite {
+                        rewritten_html(&file_path, &origins, &route_path, hashes.as_ref())
+                            .await
+                            .map(|html| {
+                                let etag = etag::body_etag(&html);
+                                (Body::from(html), Some(etag))
+                            })
+                    } else if is_fresh(blob_etag.as_ref()) {
+                        Ok((Body::empty(), blob_etag))
+                    } else if let range::ByteRange::Partial(start, end) = byte_range {
+                        range::file_range(&file_path, start, end)
+                            .await
+                            .map(|body| (body, blob_etag))
+                    } else {
+                        file_body(&file_path, args.stream_threshold_bytes)
+                            .await
+                            .map(|body| (body, blob_etag))
+                    }
+                    .map_err(|e| {
+                        error!("Failed to read file {}: {}", file_path.display(), e);
                         StatusCode::INTERNAL_SERVER_ERROR
                     })?;
-
-                    let mut response = Response::new(axum::body::Body::from(contents));
-                    let content_type = match file_path.extension().and_then(|e| e.to_str()) {
-                        Some("html") | Some("htm") => "text/html",
-                        Some("css") => "text/css",
-                        Some("js") => "application/javascript",
-                        Some("json") => "application/json",
-                        Some("png") => "image/png",
-                        Some("jpg") | Some("jpeg") => "image/jpeg",
-                        Some("gif") => "image/gif",
-                        Some("svg") => "image/svg+xml",
-                        Some("woff") => "font/woff",
-                        Some("woff2") => "font/woff2",
-                        _ => "application/octet-stream",
+                    let mut response = if is_fresh(etag.as_ref()) {
+                        not_modified()
+                    } else {
+                        Response::new(body)
+                    };
+                    if !rewrite {
+                        response
+                            .headers_mut()
+                            .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+                    }
+                    if let range::ByteRange::Partial(start, end) = byte_range {
+                        range::set_partial(&mut response, start, end, len);
+                    }
+                    set_file_content_type(&mut response, &file_path).await;
+                    set_cache_control(&mut response, cache_policy);
+                    set_validators(&mut response, etag, created_at);
+                    if let Some(links) = preload {
+                        response.headers_mut().insert(header::LINK, links);
+                    }
+                    if let Some(link) = canonical_path
+                        .and_then(|p| canonical_link(&request, host, &format!("{}{}", base, p)))
+                    {
+                        response.headers_mut().append(header::LINK, link);
+                    }
+                    if args.site_headers {
+                        response.headers_mut().extend(site.headers().await);
+                    }
+                    Ok(response)
+                }
+                Err(e) if is_relay_quorum_error(&e) => {
+                    warn!("Failed to serve route: {:#}", e);
+                    Err(StatusCode::SERVICE_UNAVAILABLE)
+                }
+                Err(e) if args.generate_sitemap && path_buf == "sitemap.xml" => {
+                    info!("No sitemap published ({}), generating one", e);
+                    let Ok(Some((paths, created_at))) = site.manifest_paths().await else {
+                        return Err(StatusCode::NOT_FOUND);
                     };
+                    let base_url = format!("{}://{}{}", request_scheme(&request), host, base);
+                    let xml = sitemap::sitemap_xml(&base_url, &paths, created_at);
+                    let mut response = Response::new(Body::from(xml));
+                    response
+                        .headers_mut()
+                        .insert(header::CONTENT_TYPE, "application/xml".parse().unwrap());
+                    Ok(compression::generated(response))
+                }
+                Err(e) if args.identicon_favicon && path_buf == "favicon.ico" => {
+                    info!("No favicon published ({}), serving identicon", e);
+                    let svg = identicon::identicon(&site.pubkey().await);
+                    let mut response = Response::new(Body::from(svg.to_string()));
                     response
                         .headers_mut()
-                        .insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+                        .insert(header::CONTENT_TYPE, "image/svg+xml".parse().unwrap());
                     Ok(response)
                 }
+                Err(e) if args.security_txt.is_some() && path_buf == SECURITY_TXT => {
+                    info!("No security.txt published ({}), serving the gateway's", e);
+                    security_txt_response(&args).await
+                }
+                Err(e)
+                    if path_buf == "index.html"
+                        && is_reserved(&site_host, &site_alias_map).await =>
+                {
+                    info!(
+                        "Reserved site has no index yet ({}), serving coming-soon page",
+                        e
+                    );
+                    coming_soon_response(&args).await
+                }
+                Err(e) if e.downcast_ref::<tombstones::RouteGone>().is_some() => {
+                    info!("{}", e);
+                    Err(StatusCode::GONE)
+                }
                 Err(e) => {
                     error!("Failed to serve route: {}", e);
-                    Err(StatusCode::NOT_FOUND)
+                    match site.serve_not_found_page().await {
+                        Ok(Some(content)) => {
+                            let mut response =
+                                not_found_page(content, args.stream_threshold_bytes).await?;
+                            if args.site_headers {
+                                response.headers_mut().extend(site.headers().await);
+                            }
+                            Ok(response)
+                        }
+                        Ok(None) => Err(StatusCode::NOT_FOUND),
+                        Err(e) => {
+                            warn!("Failed to serve 404 page: {}", e);
+                            Err(StatusCode::NOT_FOUND)
+                        }
+                    }
                 }
             }
         }
+        Ok(None)
+            if args.apex_http == tls::ApexHttp::Redirect && request_scheme(&request) == "http" =>
+        {
+            if args.metrics && path_str == "metrics" {
+                info!("Refusing /metrics over plain HTTP");
+                return Err(StatusCode::FORBIDDEN);
+            }
+            let path_and_query = request.uri().path_and_query().map_or("/", |p| p.as_str());
+            let path = format!("{}{}", base, path_and_query);
+            let location = tls::https_location(host, args.tls_listen.map(|a| a.port()), &path);
+            redirect(StatusCode::PERMANENT_REDIRECT, &location)
+        }
+        Ok(None) if args.security_txt.is_some() && path_str == SECURITY_TXT => {
+            security_txt_response(&args).await
+        }
+        Ok(None) if args.nip05 && path_str == ".well-known/nostr.json" => {
+            let name = query_param(&request, "name").map(percent_decode);
+            let doc = identity::nostr_json(&*site_alias_map.read().await, name.as_deref());
+            Ok(identity_response(doc, "application/json"))
+        }
+        Ok(None) if args.nip05 && path_str == ".well-known/webfinger" => {
+            let resource = query_param(&request, "resource")
+                .map(percent_decode)
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let domain = host.split(':').next().unwrap_or(host);
+            let doc = identity::webfinger(
+                &*site_alias_map.read().await,
+                &resource,
+                request_scheme(&request),
+                domain,
+            )
+            .ok_or(StatusCode::NOT_FOUND)?;
+            Ok(identity_response(doc, "application/jrd+json"))
+        }
+        Ok(None) if args.capabilities && path_str == capabilities::PATH => {
+            let relays: Vec<RelayUrl> = client.relays().await.into_keys().collect();
+            let doc = capabilities::document(
+                &site_config,
+                &relays,
+                args.gateway,
+                &enabled_features(&args),
+            );
+            Ok(identity_response(doc, "application/json"))
+        }
+        Ok(None) if args.metrics && path_str == "metrics" => Ok(metrics_response(&site_map).await),
         Ok(None) => {
             // No subdomain - serve index.html
-            let mut response = Response::new(axum::body::Body::from(INDEX_HTML));
+            let html = if args.gateway {
+                GATEWAY_INDEX_HTML.as_str()
+            } else {
+                INDEX_HTML
+            };
+            let mut response = Response::new(Body::from(html));
             response
                 .headers_mut()
                 .insert(header::CONTENT_TYPE, "text/html".parse().unwrap());
             Ok(response)
         }
+        Err(e) if e.downcast_ref::<relays::RelaysOffline>().is_some() => {
+            info!("Not resolving {}, {}", host, e);
+            reconnecting_response(&args).await
+        }
+        Err(e) if is_relay_quorum_error(&e) => {
+            warn!("{:#}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(e) if is_reserved(&site_host, &site_alias_map).await => {
+            info!(
+                "Reserved site not published yet ({}), serving coming-soon page",
+                e
+            );
+            coming_soon_response(&args).await
+        }
         Err(_) => Err(StatusCode::NOT_FOUND),
     }
 }
@@ -0,0 +1,106 @@
+use crate::SiteMap;
+use log::info;
+use std::time::Duration;
+
+/// How often the site cache is measured against the budget
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Evicting stops once usage is back under this share of the budget, so the next few
+/// sites loaded don't trigger another round right away
+const LOW_WATERMARK_PERCENT: usize = 90;
+
+/// Keep the site cache within `budget` bytes, checking every few seconds
+pub async fn enforce_budget(site_map: SiteMap, budget: usize) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        enforce(&site_map, budget).await;
+    }
+}
+
+/// Evict from the site cache until its estimated size is within `budget`
+///
+/// The resolved routes of the least recently used sites go first, as they're cheap to
+/// resolve again from the cached manifest. Whole sites follow, least recently used first,
+/// when dropping routes isn't enough. Sites busy loading are left alone. Returns the
+/// estimated bytes in use afterwards.
+pub async fn enforce(site_map: &SiteMap, budget: usize) -> usize {
+    let mut sites: Vec<_> = site_map
+        .read()
+        .await
+        .iter()
+        .filter_map(|(key, site)| {
+            let (size, last_used) = site.memory_usage()?;
+            Some((key.clone(), site.clone(), size + key.len(), last_used))
+        })
+        .collect();
+    let mut used: usize = sites.iter().map(|(_, _, size, _)| size).sum();
+    crate::metrics::METRICS.set_memory_bytes(used);
+    if used <= budget {
+        return used;
+    }
+
+    let target = budget / 100 * LOW_WATERMARK_PERCENT;
+    sites.sort_by_key(|(_, _, _, last_used)| *last_used);
+    let mut dropped_routes = 0;
+    for (_, site, size, _) in sites.iter_mut() {
+        if used <= target {
+            break;
+        }
+        let freed = site.drop_routes();
+        if freed > 0 {
+            used -= freed;
+            *size -= freed;
+            dropped_routes += 1;
+        }
+    }
+    let mut dropped_sites = 0;
+    if used > target {
+        let mut map = site_map.write().await;
+        for (key, _, size, _) in &sites {
+            if used <= target {
+                break;
+            }
+            if map.remove(key).is_some() {
+                used -= size;
+                dropped_sites += 1;
+            }
+        }
+    }
+    info!(
+        "Memory budget of {} bytes exceeded, dropped the routes of {} sites and {} sites, ~{} bytes in use",
+        budget, dropped_routes, dropped_sites, used
+    );
+    crate::metrics::METRICS.set_memory_bytes(used);
+    crate::metrics::METRICS.record_memory_evictions(dropped_routes, dropped_sites);
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::site::{SiteConfig, SiteInfo};
+    use nostr_sdk::Client;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_enforce() {
+        let site_map = SiteMap::default();
+        let config = Arc::new(SiteConfig::default());
+        let client = Client::default();
+        for i in 0..4u8 {
+            let site = SiteInfo::new_expired(&client, &config, &[i; 32], None, None).await;
+            site_map.write().await.insert(format!("site-{}", i), site);
+        }
+        let used = enforce(&site_map, usize::MAX).await;
+        assert_eq!(site_map.read().await.len(), 4);
+
+        // Without routes to drop, the least recently used sites go until usage is under 90%
+        let budget = used / 2;
+        let after = enforce(&site_map, budget).await;
+        assert!(after <= budget / 100 * LOW_WATERMARK_PERCENT);
+        let map = site_map.read().await;
+        assert_eq!(map.len(), 1);
+        assert!(map.contains_key("site-3"));
+    }
+}
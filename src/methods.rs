@@ -0,0 +1,158 @@
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{HeaderValue, Method, StatusCode, Uri, header};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// Methods a static site gateway answers
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Only allow safe methods and reject requests carrying a body
+///
+/// Nothing served by the gateway ever reads a request body, so a body is refused
+/// before it's buffered anywhere.
+pub async fn restrict_requests(request: Request, next: Next) -> Response {
+    match *request.method() {
+        Method::GET | Method::HEAD => {}
+        Method::OPTIONS => return allow_response(StatusCode::NO_CONTENT),
+        _ => return allow_response(StatusCode::METHOD_NOT_ALLOWED),
+    }
+    if has_body(&request) {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+        return response;
+    }
+    next.run(request).await
+}
+
+/// Answer the server-wide `OPTIONS *` request with the methods the gateway supports
+///
+/// This sits outside the CORS layer, which would otherwise answer it like a preflight.
+pub async fn server_options(request: Request, next: Next) -> Response {
+    if request.method() == Method::OPTIONS && request.uri().path() == "*" {
+        return allow_response(StatusCode::NO_CONTENT);
+    }
+    next.run(request).await
+}
+
+/// Refuse request paths longer than `max` bytes with 414
+///
+/// The raw path is measured, before it's decoded, so nothing is allocated for a
+/// pathological URL and it never reaches the relays in a filter or the disk cache.
+pub fn check_path_length(uri: &Uri, max: usize) -> Result<(), StatusCode> {
+    if uri.path().len() > max {
+        log::info!("Refusing request for a path of {} bytes", uri.path().len());
+        return Err(StatusCode::URI_TOO_LONG);
+    }
+    Ok(())
+}
+
+fn has_body(request: &Request) -> bool {
+    let headers = request.headers();
+    headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .is_some_and(|len| len.to_str().map_or(true, |l| l.trim() != "0"))
+}
+
+fn allow_response(status: StatusCode) -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::Request;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(restrict_requests))
+    }
+
+    #[test]
+    fn test_check_path_length() {
+        let uri = |path: String| path.parse::<Uri>().unwrap();
+        assert_eq!(
+            check_path_length(&uri(format!("/{}", "a".repeat(99))), 100),
+            Ok(())
+        );
+        assert_eq!(
+            check_path_length(&uri(format!("/{}", "a".repeat(100))), 100),
+            Err(StatusCode::URI_TOO_LONG)
+        );
+        // Only the path counts, the query isn't used to resolve routes
+        assert_eq!(
+            check_path_length(&uri(format!("/?q={}", "a".repeat(200))), 100),
+            Ok(())
+        );
+        assert_eq!(
+            check_path_length(&uri(format!("/{}", "%41".repeat(50))), 100),
+            Err(StatusCode::URI_TOO_LONG)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_options() {
+        let app = app()
+            .layer(tower_http::cors::CorsLayer::permissive())
+            .layer(axum::middleware::from_fn(server_options));
+        let request = Request::options("*").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ALLOW], ALLOWED_METHODS);
+
+        let get = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(get).await.unwrap().status(), StatusCode::OK);
+    }
+
+    async fn status(request: Request<Body>) -> (StatusCode, Option<HeaderValue>) {
+        let response = app().oneshot(request).await.unwrap();
+        (
+            response.status(),
+            response.headers().get(header::ALLOW).cloned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_allowed_methods() {
+        let get = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(status(get).await.0, StatusCode::OK);
+
+        let head = Request::head("/").body(Body::empty()).unwrap();
+        assert_eq!(status(head).await.0, StatusCode::OK);
+
+        let options = Request::options("/").body(Body::empty()).unwrap();
+        let (code, allow) = status(options).await;
+        assert_eq!(code, StatusCode::NO_CONTENT);
+        assert_eq!(allow.unwrap(), ALLOWED_METHODS);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() {
+        let post = Request::post("/").body(Body::from("x")).unwrap();
+        let (code, allow) = status(post).await;
+        assert_eq!(code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow.unwrap(), ALLOWED_METHODS);
+
+        let get_with_body = Request::get("/")
+            .header(header::CONTENT_LENGTH, "5")
+            .body(Body::from("hello"))
+            .unwrap();
+        assert_eq!(status(get_with_body).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let chunked = Request::get("/")
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(chunked).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Global metrics registry, rendered in the Prometheus text format
+pub static METRICS: once_cell::sync::Lazy<Metrics> = once_cell::sync::Lazy::new(Metrics::default);
+
+/// Outcome of a single relay query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelayOutcome {
+    Ok,
+    Timeout,
+    Error,
+}
+
+#[derive(Default)]
+struct RelayStats {
+    requests: u64,
+    wins: u64,
+    timeouts: u64,
+    errors: u64,
+    latency_sum: f64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    relays: Mutex<BTreeMap<String, RelayStats>>,
+    verified: AtomicU64,
+    verify_mismatches: AtomicU64,
+    memory_bytes: AtomicU64,
+    route_evictions: AtomicU64,
+    site_evictions: AtomicU64,
+    disk_cache_bytes: AtomicU64,
+    disk_evictions: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    /// Successful and failed downloads per Blossom server
+    blossom: Mutex<BTreeMap<String, (u64, u64)>>,
+    route_resolves: AtomicU64,
+    route_resolve_micros: AtomicU64,
+    sites_cached: AtomicU64,
+}
+
+impl Metrics {
+    /// Record the outcome and latency of querying a relay
+    pub fn record_relay(&self, relay: &str, outcome: RelayOutcome, elapsed: Duration) {
+        let mut relays = self.relays.lock().unwrap();
+        let stats = relays.entry(relay.to_string()).or_default();
+        stats.requests += 1;
+        stats.latency_sum += elapsed.as_secs_f64();
+        match outcome {
+            RelayOutcome::Ok => {}
+            RelayOutcome::Timeout => stats.timeouts += 1,
+            RelayOutcome::Error => stats.errors += 1,
+        }
+    }
+
+    /// Record that a relay returned the event that was ultimately used
+    pub fn record_relay_win(&self, relay: &str) {
+        let mut relays = self.relays.lock().unwrap();
+        relays.entry(relay.to_string()).or_default().wins += 1;
+    }
+
+    /// Record the result of checking a served file against its route hash
+    pub fn record_verify(&self, matched: bool) {
+        if matched {
+            self.verified.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.verify_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Set the estimated bytes held by the site cache
+    pub fn set_memory_bytes(&self, bytes: usize) {
+        self.memory_bytes.store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record sites whose routes were dropped, and sites dropped, to stay within the memory budget
+    pub fn record_memory_evictions(&self, routes: u64, sites: u64) {
+        self.route_evictions.fetch_add(routes, Ordering::Relaxed);
+        self.site_evictions.fetch_add(sites, Ordering::Relaxed);
+    }
+
+    /// Set the bytes of blobs held by the disk cache
+    pub fn set_disk_cache_bytes(&self, bytes: u64) {
+        self.disk_cache_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Record blob files deleted to stay within the disk cache cap
+    pub fn record_disk_evictions(&self, files: u64) {
+        self.disk_evictions.fetch_add(files, Ordering::Relaxed);
+    }
+
+    /// Record whether a served blob was already in the cache or had to be downloaded
+    pub fn record_cache(&self, hit: bool) {
+        if hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record whether a Blossom server served a blob asked from it
+    pub fn record_blossom(&self, server: &str, ok: bool) {
+        let mut servers = self.blossom.lock().unwrap();
+        let (successes, failures) = servers.entry(server.to_string()).or_default();
+        if ok {
+            *successes += 1;
+        } else {
+            *failures += 1;
+        }
+    }
+
+    /// Record how long resolving a requested path to its route took
+    pub fn record_route_resolve(&self, elapsed: Duration) {
+        self.route_resolves.fetch_add(1, Ordering::Relaxed);
+        self.route_resolve_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Set the number of sites currently held by the site cache
+    pub fn set_sites_cached(&self, sites: usize) {
+        self.sites_cached.store(sites as u64, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let relays = self.relays.lock().unwrap();
+        let mut out = String::new();
+        let mut family =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&RelayStats) -> f64| {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} {}", name, kind);
+                for (relay, stats) in relays.iter() {
+                    let _ = writeln!(
+                        out,
+                        "{}{{relay=\"{}\"}} {}",
+                        name,
+                        escape_label(relay),
+                        value(stats)
+                    );
+                }
+            };
+        family(
+            "nsite_relay_requests_total",
+            "counter",
+            "Event fetches sent to each relay",
+            &|s| s.requests as f64,
+        );
+        family(
+            "nsite_relay_wins_total",
+            "counter",
+            "Fetches where the relay returned the event that was used",
+            &|s| s.wins as f64,
+        );
+        family(
+            "nsite_relay_timeouts_total",
+            "counter",
+            "Fetches that timed out",
+            &|s| s.timeouts as f64,
+        );
+        family(
+            "nsite_relay_errors_total",
+            "counter",
+            "Fetches that failed",
+            &|s| s.errors as f64,
+        );
+        family(
+            "nsite_relay_fetch_duration_seconds_sum",
+            "counter",
+            "Total time spent fetching from each relay",
+            &|s| s.latency_sum,
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_debug_verify_total Served files hashed by --debug-verify-log"
+        );
+        let _ = writeln!(out, "# TYPE nsite_debug_verify_total counter");
+        let _ = writeln!(
+            out,
+            "nsite_debug_verify_total{{result=\"ok\"}} {}",
+            self.verified.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "nsite_debug_verify_total{{result=\"mismatch\"}} {}",
+            self.verify_mismatches.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_memory_bytes Estimated bytes held by the site cache, measured with --max-memory-bytes"
+        );
+        let _ = writeln!(out, "# TYPE nsite_memory_bytes gauge");
+        let _ = writeln!(
+            out,
+            "nsite_memory_bytes {}",
+            self.memory_bytes.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_memory_evictions_total Evictions to stay within --max-memory-bytes"
+        );
+        let _ = writeln!(out, "# TYPE nsite_memory_evictions_total counter");
+        let _ = writeln!(
+            out,
+            "nsite_memory_evictions_total{{kind=\"routes\"}} {}",
+            self.route_evictions.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "nsite_memory_evictions_total{{kind=\"site\"}} {}",
+            self.site_evictions.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_disk_cache_bytes Bytes of blobs in the disk cache, tracked with --cache-max-bytes"
+        );
+        let _ = writeln!(out, "# TYPE nsite_disk_cache_bytes gauge");
+        let _ = writeln!(
+            out,
+            "nsite_disk_cache_bytes {}",
+            self.disk_cache_bytes.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_disk_cache_evictions_total Blob files deleted to stay within --cache-max-bytes"
+        );
+        let _ = writeln!(out, "# TYPE nsite_disk_cache_evictions_total counter");
+        let _ = writeln!(
+            out,
+            "nsite_disk_cache_evictions_total {}",
+            self.disk_evictions.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_cache_requests_total Served blobs found in the cache or downloaded"
+        );
+        let _ = writeln!(out, "# TYPE nsite_cache_requests_total counter");
+        let _ = writeln!(
+            out,
+            "nsite_cache_requests_total{{result=\"hit\"}} {}",
+            self.cache_hits.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "nsite_cache_requests_total{{result=\"miss\"}} {}",
+            self.cache_misses.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_blossom_downloads_total Blobs asked from each Blossom server, by outcome"
+        );
+        let _ = writeln!(out, "# TYPE nsite_blossom_downloads_total counter");
+        for (server, (successes, failures)) in self.blossom.lock().unwrap().iter() {
+            let server = escape_label(server);
+            let _ = writeln!(
+                out,
+                "nsite_blossom_downloads_total{{server=\"{}\",result=\"ok\"}} {}",
+                server, successes
+            );
+            let _ = writeln!(
+                out,
+                "nsite_blossom_downloads_total{{server=\"{}\",result=\"error\"}} {}",
+                server, failures
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP nsite_route_resolve_duration_seconds Time spent resolving requested paths to routes"
+        );
+        let _ = writeln!(out, "# TYPE nsite_route_resolve_duration_seconds summary");
+        let _ = writeln!(
+            out,
+            "nsite_route_resolve_duration_seconds_sum {}",
+            self.route_resolve_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "nsite_route_resolve_duration_seconds_count {}",
+            self.route_resolves.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP nsite_sites_cached Sites currently held by the site cache"
+        );
+        let _ = writeln!(out, "# TYPE nsite_sites_cached gauge");
+        let _ = writeln!(
+            out,
+            "nsite_sites_cached {}",
+            self.sites_cached.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+/// Escape a Prometheus label value
+pub(crate) fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_relay_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_relay(
+            "wss://a.example",
+            RelayOutcome::Ok,
+            Duration::from_millis(500),
+        );
+        metrics.record_relay(
+            "wss://a.example",
+            RelayOutcome::Timeout,
+            Duration::from_secs(1),
+        );
+        metrics.record_relay("wss://b.example", RelayOutcome::Error, Duration::ZERO);
+        metrics.record_relay_win("wss://a.example");
+
+        let out = metrics.render();
+        assert!(out.contains("nsite_relay_requests_total{relay=\"wss://a.example\"} 2"));
+        assert!(out.contains("nsite_relay_wins_total{relay=\"wss://a.example\"} 1"));
+        assert!(out.contains("nsite_relay_timeouts_total{relay=\"wss://a.example\"} 1"));
+        assert!(out.contains("nsite_relay_errors_total{relay=\"wss://b.example\"} 1"));
+        assert!(
+            out.contains("nsite_relay_fetch_duration_seconds_sum{relay=\"wss://a.example\"} 1.5")
+        );
+    }
+
+    #[test]
+    fn test_render_verify_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_verify(true);
+        metrics.record_verify(true);
+        metrics.record_verify(false);
+
+        let out = metrics.render();
+        assert!(out.contains("nsite_debug_verify_total{result=\"ok\"} 2"));
+        assert!(out.contains("nsite_debug_verify_total{result=\"mismatch\"} 1"));
+    }
+
+    #[test]
+    fn test_render_memory_metrics() {
+        let metrics = Metrics::default();
+        metrics.set_memory_bytes(4096);
+        metrics.record_memory_evictions(3, 1);
+
+        let out = metrics.render();
+        assert!(out.contains("nsite_memory_bytes 4096"));
+        assert!(out.contains("nsite_memory_evictions_total{kind=\"routes\"} 3"));
+        assert!(out.contains("nsite_memory_evictions_total{kind=\"site\"} 1"));
+    }
+
+    #[test]
+    fn test_render_disk_cache_metrics() {
+        let metrics = Metrics::default();
+        metrics.set_disk_cache_bytes(1 << 20);
+        metrics.record_disk_evictions(2);
+
+        let out = metrics.render();
+        assert!(out.contains("nsite_disk_cache_bytes 1048576"));
+        assert!(out.contains("nsite_disk_cache_evictions_total 2"));
+    }
+
+    #[test]
+    fn test_render_serving_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_cache(true);
+        metrics.record_cache(true);
+        metrics.record_cache(false);
+        metrics.record_blossom("https://a.example/", true);
+        metrics.record_blossom("https://a.example/", false);
+        metrics.record_blossom("https://b.example/", false);
+        metrics.record_route_resolve(Duration::from_millis(250));
+        metrics.record_route_resolve(Duration::from_millis(750));
+        metrics.set_sites_cached(7);
+
+        let out = metrics.render();
+        assert!(out.contains("nsite_cache_requests_total{result=\"hit\"} 2"));
+        assert!(out.contains("nsite_cache_requests_total{result=\"miss\"} 1"));
+        assert!(out.contains(
+            "nsite_blossom_downloads_total{server=\"https://a.example/\",result=\"ok\"} 1"
+        ));
+        assert!(out.contains(
+            "nsite_blossom_downloads_total{server=\"https://a.example/\",result=\"error\"} 1"
+        ));
+        assert!(out.contains(
+            "nsite_blossom_downloads_total{server=\"https://b.example/\",result=\"error\"} 1"
+        ));
+        assert!(out.contains("nsite_route_resolve_duration_seconds_sum 1\n"));
+        assert!(out.contains("nsite_route_resolve_duration_seconds_count 2"));
+        assert!(out.contains("nsite_sites_cached 7"));
+    }
+}
@@ -0,0 +1,340 @@
+use crate::SiteAliasMap;
+use crate::alias::SiteAlias;
+use anyhow::{Result, anyhow, bail};
+use futures::StreamExt;
+use log::{info, warn};
+use nostr_sdk::PublicKey;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of NIP-05 providers a name is looked up at
+pub const MAX_NIP05_PROVIDERS: usize = 4;
+
+/// How long a name no provider knows is remembered, so it isn't looked up on every request
+const MISS_TTL: Duration = Duration::from_secs(300);
+
+/// Bounds on the requests sent to NIP-05 providers
+#[derive(Clone, Copy, Debug)]
+pub struct FetchLimits {
+    /// Largest `nostr.json` read from a provider
+    pub max_bytes: usize,
+    /// Allow providers resolving to loopback, private or link-local addresses
+    pub allow_private: bool,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024,
+            allow_private: false,
+        }
+    }
+}
+
+/// Names recently not found at any provider
+static MISSES: once_cell::sync::Lazy<Mutex<HashMap<String, Instant>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Names aliased from a NIP-05 lookup and when they were looked up
+static RESOLVED: once_cell::sync::Lazy<Mutex<HashMap<String, Instant>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Resolve a subdomain without an alias at the NIP-05 providers and alias it to the result
+///
+/// Providers are tried in order and the first that knows the name wins. The whole lookup
+/// is bounded by `timeout` and each request by `limits`. Names resolved more than `ttl` ago
+/// are looked up again and dropped if no provider knows them anymore, a zero `ttl` keeps
+/// them until restart.
+pub async fn resolve_alias(
+    name: &str,
+    providers: &[String],
+    timeout: Duration,
+    ttl: Duration,
+    limits: &FetchLimits,
+    alias_map: &SiteAliasMap,
+) {
+    if providers.is_empty() || !is_local_name(name) {
+        return;
+    }
+    let expired = !ttl.is_zero()
+        && RESOLVED
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|t| t.elapsed() >= ttl);
+    if !expired && crate::alias::lookup(&*alias_map.read().await, name).is_some() {
+        return;
+    }
+    if MISSES
+        .lock()
+        .unwrap()
+        .get(name)
+        .is_some_and(|t| t.elapsed() < MISS_TTL)
+    {
+        return;
+    }
+
+    let pubkey = match tokio::time::timeout(timeout, lookup(name, providers, limits)).await {
+        Ok(pubkey) => pubkey,
+        Err(_) => {
+            warn!("NIP-05 lookup of {} timed out after {:?}", name, timeout);
+            None
+        }
+    };
+    let Some((pubkey, provider)) = pubkey else {
+        if expired {
+            info!(
+                "{} is no longer known to any NIP-05 provider, dropping its alias",
+                name
+            );
+            RESOLVED.lock().unwrap().remove(name);
+            alias_map.write().await.remove(name);
+        }
+        let mut misses = MISSES.lock().unwrap();
+        misses.retain(|_, t| t.elapsed() < MISS_TTL);
+        misses.insert(name.to_string(), Instant::now());
+        return;
+    };
+    info!(
+        "Resolved {} to {} at {}",
+        name,
+        hex::encode(pubkey),
+        provider
+    );
+    RESOLVED
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), Instant::now());
+    alias_map
+        .write()
+        .await
+        .insert(name.to_string(), SiteAlias::new(pubkey));
+}
+
+/// Look up a name at each provider in turn
+async fn lookup<'a>(
+    name: &str,
+    providers: &'a [String],
+    limits: &FetchLimits,
+) -> Option<([u8; 32], &'a str)> {
+    for provider in providers.iter().take(MAX_NIP05_PROVIDERS) {
+        match fetch(name, provider, limits).await {
+            Ok(Some(pubkey)) => return Some((pubkey, provider)),
+            Ok(None) => {}
+            Err(e) => warn!("NIP-05 lookup of {} at {} failed: {}", name, provider, e),
+        }
+    }
+    None
+}
+
+/// Fetch the `nostr.json` of a provider and the public key it lists for `name`
+///
+/// The provider is connected to at the addresses checked here, so DNS can't point the
+/// request anywhere else afterwards, and redirects aren't followed.
+async fn fetch(name: &str, provider: &str, limits: &FetchLimits) -> Result<Option<[u8; 32]>> {
+    let url = reqwest::Url::parse(&format!(
+        "https://{}/.well-known/nostr.json?name={}",
+        provider, name
+    ))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    let addrs = resolve(host, url.port_or_known_default().unwrap_or(443), limits).await?;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(host.trim_start_matches('[').trim_end_matches(']'), &addrs)
+        .build()?;
+    let response = client.get(url.clone()).send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} returned {}", url, response.status()));
+    }
+    let doc: Value = serde_json::from_slice(&read_limited(response, limits.max_bytes).await?)?;
+    Ok(pubkey_for(&doc, name))
+}
+
+/// Addresses of a provider, refused unless all are public or `limits` allows private ones
+async fn resolve(host: &str, port: u16, limits: &FetchLimits) -> Result<Vec<SocketAddr>> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    if addrs.is_empty() {
+        bail!("{} has no addresses", host);
+    }
+    if !limits.allow_private
+        && let Some(addr) = addrs.iter().find(|a| !crate::relays::is_public_ip(a.ip()))
+    {
+        bail!("{} resolves to non-public address {}", host, addr.ip());
+    }
+    Ok(addrs)
+}
+
+/// Read a response body, failing once it's larger than `max_bytes`
+async fn read_limited(response: reqwest::Response, max_bytes: usize) -> Result<Vec<u8>> {
+    if let Some(len) = response.content_length()
+        && len > max_bytes as u64
+    {
+        bail!(
+            "nostr.json of {} bytes is over the {} byte limit",
+            len,
+            max_bytes
+        );
+    }
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > max_bytes {
+            bail!("nostr.json is over the {} byte limit", max_bytes);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Public key a `nostr.json` document lists for `name`
+fn pubkey_for(doc: &Value, name: &str) -> Option<[u8; 32]> {
+    let hex = doc.get("names")?.get(name)?.as_str()?;
+    PublicKey::from_hex(hex).ok().map(|pk| pk.to_bytes())
+}
+
+/// Whether a subdomain can be a NIP-05 local name rather than an encoded pubkey
+///
+/// npubs, base36 named sites and hex pubkeys are all 50 characters or longer.
+fn is_local_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < 50
+        && !name.starts_with("npub1")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pubkey_for() {
+        let hex = "a".repeat(64);
+        let doc = json!({ "names": { "alice": hex, "bob": "nope" } });
+        assert_eq!(
+            pubkey_for(&doc, "alice"),
+            Some(PublicKey::from_hex(&hex).unwrap().to_bytes())
+        );
+        assert_eq!(pubkey_for(&doc, "bob"), None);
+        assert_eq!(pubkey_for(&doc, "carol"), None);
+        assert_eq!(pubkey_for(&json!({}), "alice"), None);
+    }
+
+    #[test]
+    fn test_is_local_name() {
+        assert!(is_local_name("alice"));
+        assert!(is_local_name("bob_2"));
+        assert!(!is_local_name("npub1abc"));
+        assert!(!is_local_name(&"a".repeat(64)));
+        assert!(!is_local_name("Alice"));
+        assert!(!is_local_name(""));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_alias_skips() {
+        let alias_map = SiteAliasMap::default();
+        let limits = FetchLimits::default();
+        resolve_alias(
+            "alice",
+            &[],
+            Duration::from_secs(1),
+            Duration::ZERO,
+            &limits,
+            &alias_map,
+        )
+        .await;
+        resolve_alias(
+            "npub1abc",
+            &["example.invalid".to_string()],
+            Duration::from_secs(1),
+            Duration::ZERO,
+            &limits,
+            &alias_map,
+        )
+        .await;
+        assert!(alias_map.read().await.is_empty());
+        let misses = MISSES.lock().unwrap();
+        assert!(!misses.contains_key("alice") && !misses.contains_key("npub1abc"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_alias_dropped() {
+        let alias_map = SiteAliasMap::default();
+        let alias = SiteAlias::new([1u8; 32]);
+        alias_map
+            .write()
+            .await
+            .insert("dave".to_string(), alias.clone());
+        alias_map.write().await.insert("erin".to_string(), alias);
+        RESOLVED.lock().unwrap().insert(
+            "dave".to_string(),
+            Instant::now() - Duration::from_secs(120),
+        );
+        RESOLVED
+            .lock()
+            .unwrap()
+            .insert("erin".to_string(), Instant::now());
+
+        let providers = ["127.0.0.1:1".to_string()];
+        let ttl = Duration::from_secs(60);
+        let limits = FetchLimits::default();
+        resolve_alias(
+            "dave",
+            &providers,
+            Duration::from_secs(1),
+            ttl,
+            &limits,
+            &alias_map,
+        )
+        .await;
+        resolve_alias(
+            "erin",
+            &providers,
+            Duration::from_secs(1),
+            ttl,
+            &limits,
+            &alias_map,
+        )
+        .await;
+        let aliases = alias_map.read().await;
+        assert!(!aliases.contains_key("dave"));
+        assert!(aliases.contains_key("erin"));
+    }
+
+    #[tokio::test]
+    async fn test_private_providers_refused() {
+        let limits = FetchLimits::default();
+        for provider in [
+            "127.0.0.1:8443",
+            "localhost",
+            "10.1.2.3",
+            "[::1]:8443",
+            "169.254.169.254",
+        ] {
+            let e = fetch("alice", provider, &limits).await.unwrap_err();
+            assert!(e.to_string().contains("non-public"), "{}: {}", provider, e);
+        }
+        let allowed = FetchLimits {
+            allow_private: true,
+            ..limits
+        };
+        let addrs = resolve("127.0.0.1", 8443, &allowed).await.unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 8443))]);
+    }
+
+    #[tokio::test]
+    async fn test_read_limited() {
+        let response = |body: &'static str| reqwest::Response::from(http::Response::new(body));
+        assert_eq!(read_limited(response("{}"), 2).await.unwrap(), b"{}");
+        assert!(read_limited(response("{\"names\":{}}"), 8).await.is_err());
+    }
+}
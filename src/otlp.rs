@@ -0,0 +1,279 @@
+use std::fmt::Display;
+use std::future::Future;
+
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::{FutureExt, Status, TraceContextExt, Tracer};
+#[cfg(feature = "otlp")]
+use opentelemetry::{Context, KeyValue};
+
+/// Tracer provider set once by [`init`], its tracer starts every span
+#[cfg(feature = "otlp")]
+static PROVIDER: once_cell::sync::OnceCell<(
+    opentelemetry_sdk::trace::SdkTracerProvider,
+    opentelemetry_sdk::trace::SdkTracer,
+)> = once_cell::sync::OnceCell::new();
+
+/// A timed operation of a trace, ended when dropped
+///
+/// Spans are children of the span whose [`Span::scope`] they're started in, or start a
+/// new trace. Without the `otlp` feature or [`init`] they're empty and cost next to nothing.
+pub struct Span {
+    #[cfg(feature = "otlp")]
+    cx: Option<Context>,
+}
+
+impl Span {
+    /// Start an internal span named `name`
+    pub fn start(name: &'static str) -> Self {
+        Self::with_kind(name, Kind::Internal)
+    }
+
+    /// Start a span for a request the gateway makes, to a relay or a Blossom server
+    pub fn client(name: &'static str) -> Self {
+        Self::with_kind(name, Kind::Client)
+    }
+
+    /// Start a span for a request the gateway answers, continuing the trace of its
+    /// W3C `traceparent` header when `trust_parent` is set
+    pub fn server(name: &'static str, headers: &http::HeaderMap, trust_parent: bool) -> Self {
+        #[cfg(feature = "otlp")]
+        {
+            let parent = if trust_parent {
+                parent_context(headers)
+            } else {
+                Context::new()
+            };
+            Self::started(name, Kind::Server, &parent)
+        }
+        #[cfg(not(feature = "otlp"))]
+        {
+            let _ = (name, headers, trust_parent);
+            Self {}
+        }
+    }
+
+    fn with_kind(name: &'static str, kind: Kind) -> Self {
+        #[cfg(feature = "otlp")]
+        {
+            Self::started(name, kind, &Context::current())
+        }
+        #[cfg(not(feature = "otlp"))]
+        {
+            let _ = (name, kind);
+            Self {}
+        }
+    }
+
+    #[cfg(feature = "otlp")]
+    fn started(name: &'static str, kind: Kind, parent: &Context) -> Self {
+        let Some((_, tracer)) = PROVIDER.get() else {
+            return Self { cx: None };
+        };
+        let span = tracer
+            .span_builder(name)
+            .with_kind(match kind {
+                Kind::Internal => opentelemetry::trace::SpanKind::Internal,
+                Kind::Client => opentelemetry::trace::SpanKind::Client,
+                Kind::Server => opentelemetry::trace::SpanKind::Server,
+            })
+            .start_with_context(tracer, parent);
+        Self {
+            cx: Some(parent.with_span(span)),
+        }
+    }
+
+    /// Add an attribute
+    pub fn attr(mut self, key: &'static str, value: impl Display) -> Self {
+        self.set_attr(key, value);
+        self
+    }
+
+    /// Add an attribute to a span already started
+    pub fn set_attr(&mut self, key: &'static str, value: impl Display) {
+        #[cfg(feature = "otlp")]
+        if let Some(cx) = &self.cx {
+            cx.span()
+                .set_attribute(KeyValue::new(key, value.to_string()));
+        }
+        #[cfg(not(feature = "otlp"))]
+        let _ = (key, value);
+    }
+
+    /// Mark the span as failed with `error`
+    pub fn fail(&mut self, error: impl Display) {
+        #[cfg(feature = "otlp")]
+        if let Some(cx) = &self.cx {
+            cx.span().set_status(Status::error(format!("{:#}", error)));
+        }
+        #[cfg(not(feature = "otlp"))]
+        let _ = error;
+    }
+
+    /// Run `fut` with this span as the parent of the spans started in it
+    pub async fn scope<F: Future>(&self, fut: F) -> F::Output {
+        #[cfg(feature = "otlp")]
+        if let Some(cx) = &self.cx {
+            return fut.with_context(cx.clone()).await;
+        }
+        fut.await
+    }
+
+    /// Run `fut` in this span, which ends with it and fails if it fails
+    pub async fn run<T, E: Display>(
+        mut self,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let result = self.scope(fut).await;
+        if let Err(e) = &result {
+            self.fail(e);
+        }
+        result
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(cx) = self.cx.take() {
+            cx.span().end();
+        }
+    }
+}
+
+/// What a span stands for, as OpenTelemetry tells them apart
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+enum Kind {
+    Internal,
+    Client,
+    Server,
+}
+
+/// Keep the spans `fut` starts in the trace of the current span, for work that's spawned
+/// or awaited by other tasks than the one starting it
+pub fn in_current_trace<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "otlp")]
+    {
+        fut.with_current_context()
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        fut
+    }
+}
+
+/// Export spans to the OTLP/HTTP collector at `endpoint` from now on, for `--otlp-endpoint`
+///
+/// Spans are posted as protobuf to `<endpoint>/v1/traces`, unless the endpoint names that
+/// path already, in batches by a background thread. Call [`shutdown`] before exiting so
+/// the spans still queued are sent.
+#[cfg(feature = "otlp")]
+pub fn init(endpoint: nostr_sdk::Url, service: &str) -> anyhow::Result<()> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let url = if endpoint.path().ends_with("/v1/traces") {
+        endpoint
+    } else {
+        let mut url = endpoint;
+        let path = format!("{}/v1/traces", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+        url
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
+        .with_endpoint(url.as_str())
+        .build()?;
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(service.to_string())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    if PROVIDER.set((provider, tracer)).is_ok() {
+        log::info!("Exporting traces to {}", url);
+    }
+    Ok(())
+}
+
+/// Send the spans still queued and stop exporting, blocks until the collector answered
+#[cfg(feature = "otlp")]
+pub fn shutdown() {
+    if let Some((provider, _)) = PROVIDER.get()
+        && let Err(e) = provider.shutdown()
+    {
+        log::warn!("Failed to export the last traces, {}", e);
+    }
+}
+
+/// Trace a request continues, from its W3C `traceparent` and `tracestate` headers
+#[cfg(feature = "otlp")]
+fn parent_context(headers: &http::HeaderMap) -> Context {
+    use opentelemetry::propagation::TextMapPropagator;
+
+    struct Headers<'a>(&'a http::HeaderMap);
+
+    impl opentelemetry::propagation::Extractor for Headers<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    opentelemetry_sdk::propagation::TraceContextPropagator::new()
+        .extract_with_context(&Context::new(), &Headers(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disabled_spans_are_empty() {
+        let span = Span::start("load_route").attr("nsite.path", "/index.html");
+        #[cfg(feature = "otlp")]
+        assert!(span.cx.is_none());
+        let result: Result<u8, anyhow::Error> = span.run(async { Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+        let server = Span::server("request", &http::HeaderMap::new(), true);
+        drop(server);
+        assert_eq!(in_current_trace(async { 2 }).await, 2);
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_parent_context() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let parent = parent_context(&headers);
+        let context = parent.span().span_context().clone();
+        assert!(context.is_remote());
+        assert_eq!(
+            context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(context.span_id().to_string(), "00f067aa0ba902b7");
+
+        for invalid in [
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "not a traceparent",
+        ] {
+            headers.insert("traceparent", invalid.parse().unwrap());
+            let parent = parent_context(&headers);
+            assert!(!parent.span().span_context().is_valid(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use lol_html::{HtmlRewriter, Settings, element};
+use std::cell::RefCell;
+
+/// Site paths of the assets an HTML page references, in document order
+///
+/// Relative references are resolved against `page_path`, external URLs are skipped.
+pub fn asset_paths(html: &[u8], page_path: &str) -> Result<Vec<String>> {
+    let paths = RefCell::new(Vec::new());
+    let push = |value: Option<String>| {
+        if let Some(path) = value.and_then(|v| resolve(&v, page_path)) {
+            let mut paths = paths.borrow_mut();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    };
+    {
+        let mut rewriter = HtmlRewriter::new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("link[href]", |el| {
+                        let rel = el.get_attribute("rel").unwrap_or_default();
+                        if matches!(
+                            rel.as_str(),
+                            "stylesheet" | "icon" | "preload" | "modulepreload"
+                        ) {
+                            push(el.get_attribute("href"));
+                        }
+                        Ok(())
+                    }),
+                    element!("script[src], img[src]", |el| {
+                        push(el.get_attribute("src"));
+                        Ok(())
+                    }),
+                ],
+                ..Settings::new()
+            },
+            |_: &[u8]| {},
+        );
+        rewriter.write(html)?;
+        rewriter.end()?;
+    }
+    Ok(paths.into_inner())
+}
+
+/// Site paths of the pages an HTML page links to, in document order
+///
+/// Like [`asset_paths`], external links are skipped. Paths are spelled the way the gateway
+/// keys routes for requests, so links to `/` and `/docs/` become `/index.html` and `/docs/`.
+pub fn link_paths(html: &[u8], page_path: &str) -> Result<Vec<String>> {
+    let paths = RefCell::new(Vec::new());
+    {
+        let mut rewriter = HtmlRewriter::new(
+            Settings {
+                element_content_handlers: vec![element!("a[href]", |el| {
+                    let href = el.get_attribute("href").unwrap_or_default();
+                    if let Some(path) = resolve(&href, page_path) {
+                        let is_dir = href
+                            .split(['?', '#'])
+                            .next()
+                            .is_some_and(|h| h.ends_with('/'));
+                        let path = match path.as_str() {
+                            "/" => "/index.html".to_string(),
+                            _ if is_dir => format!("{}/", path),
+                            _ => path,
+                        };
+                        let mut paths = paths.borrow_mut();
+                        if path != page_path && !paths.contains(&path) {
+                            paths.push(path);
+                        }
+                    }
+                    Ok(())
+                })],
+                ..Settings::new()
+            },
+            |_: &[u8]| {},
+        );
+        rewriter.write(html)?;
+        rewriter.end()?;
+    }
+    Ok(paths.into_inner())
+}
+
+/// `Link` header preloading the assets of a page, for at most `limit` assets
+///
+/// CDNs and proxies that support Early Hints turn these into a `103` response.
+pub fn preload_links(paths: &[String], limit: usize) -> Option<String> {
+    let links: Vec<String> = paths
+        .iter()
+        .take(limit)
+        .map(|path| match preload_as(path) {
+            Some("font") => format!("<{}>; rel=preload; as=font; crossorigin", path),
+            Some(kind) => format!("<{}>; rel=preload; as={}", path, kind),
+            None => format!("<{}>; rel=preload", path),
+        })
+        .collect();
+    (!links.is_empty()).then(|| links.join(", "))
+}
+
+/// Preload destination of an asset path, from its extension
+fn preload_as(path: &str) -> Option<&'static str> {
+    let kind = match path.rsplit_once('.')?.1 {
+        "css" => "style",
+        "js" | "mjs" => "script",
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" | "ico" => "image",
+        "woff" | "woff2" | "ttf" | "otf" => "font",
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// Resolve a reference from a page to an absolute site path
+pub fn resolve(reference: &str, page_path: &str) -> Option<String> {
+    let reference = reference.trim();
+    let reference = reference.split(['?', '#']).next()?;
+    if reference.is_empty() || reference.starts_with("//") || reference.contains(':') {
+        return None;
+    }
+    let joined = if reference.starts_with('/') {
+        reference.to_string()
+    } else {
+        let dir = &page_path[..page_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}", dir, reference)
+    };
+
+    // Normalize . and .. segments
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in joined.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("/a.css", "/docs/index.html").as_deref(),
+            Some("/a.css")
+        );
+        assert_eq!(
+            resolve("b.js?v=1", "/docs/index.html").as_deref(),
+            Some("/docs/b.js")
+        );
+        assert_eq!(
+            resolve("../img/c.png", "/docs/index.html").as_deref(),
+            Some("/img/c.png")
+        );
+        assert_eq!(resolve("https://cdn.example/a.js", "/index.html"), None);
+        assert_eq!(resolve("//cdn.example/a.js", "/index.html"), None);
+        assert_eq!(resolve("data:image/png;base64,AAAA", "/index.html"), None);
+    }
+
+    #[test]
+    fn test_link_paths() {
+        let html =
+            br#"<nav><a href="/">Home</a><a href="about">About</a><a href="/docs/#intro">Docs</a>
+            <a href="https://example.com/">Elsewhere</a><a href="mailto:a@example.com">Mail</a>
+            <a href="/index.html">Self</a><a href="about">Again</a></nav>"#;
+        assert_eq!(
+            link_paths(html, "/index.html").unwrap(),
+            vec!["/about", "/docs/"]
+        );
+    }
+
+    #[test]
+    fn test_preload_links() {
+        let paths: Vec<String> = ["/style.css", "/app.js", "/f.woff2", "/data.bin"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            preload_links(&paths, 3).unwrap(),
+            "</style.css>; rel=preload; as=style, </app.js>; rel=preload; as=script, \
+             </f.woff2>; rel=preload; as=font; crossorigin"
+        );
+        assert_eq!(
+            preload_links(&paths[3..], 8).unwrap(),
+            "</data.bin>; rel=preload"
+        );
+        assert_eq!(preload_links(&[], 8), None);
+    }
+
+    #[test]
+    fn test_asset_paths() {
+        let html = br#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <link rel="canonical" href="/other.html">
+            <script src="app.js"></script>
+            <script src="https://cdn.example/lib.js"></script>
+        </head><body><img src="/style.css"><img src="logo.png"><a href="/page.html">x</a></body></html>"#;
+        assert_eq!(
+            asset_paths(html, "/index.html").unwrap(),
+            vec!["/style.css", "/app.js", "/logo.png"]
+        );
+    }
+}
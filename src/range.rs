@@ -0,0 +1,188 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::Response;
+use nostr_sdk::Timestamp;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Part of a file a request asks for with its `Range` header
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range, the whole file is sent with 200
+    Full,
+    /// Offsets of the first and last byte to send with 206
+    Partial(u64, u64),
+    /// A range starting past the end of the file, refused with 416
+    Unsatisfiable,
+}
+
+/// Byte range of a file of `len` bytes requested in `headers`
+///
+/// Only a single `bytes` range is served, multiple ranges and malformed ones get the whole
+/// file, which RFC 9110 allows. An `If-Range` that doesn't match the current `etag`
+/// (strongly) or `last_modified` date also gets the whole file, as the client's partial
+/// copy is out of date.
+pub fn requested(
+    headers: &HeaderMap,
+    len: u64,
+    etag: Option<&HeaderValue>,
+    last_modified: Option<Timestamp>,
+) -> ByteRange {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return ByteRange::Full;
+    };
+    if let Some(if_range) = headers.get(header::IF_RANGE) {
+        let current = if if_range.as_bytes().starts_with(b"\"") {
+            etag.is_some_and(|e| e == if_range)
+        } else {
+            last_modified.is_some_and(|t| crate::cache_control::last_modified(t) == if_range)
+        };
+        if !current {
+            return ByteRange::Full;
+        }
+    }
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=-N asks for the last N bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => match suffix {
+            0 => return ByteRange::Unsatisfiable,
+            n => (len.saturating_sub(n), len.saturating_sub(1)),
+        },
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        _ => return ByteRange::Full,
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end)
+}
+
+/// Body of the bytes `start` to `end` of a file, both included
+pub async fn file_range(file_path: &Path, start: u64, end: u64) -> std::io::Result<Body> {
+    let mut file = tokio::fs::File::open(file_path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    Ok(Body::from_stream(ReaderStream::new(
+        file.take(end - start + 1),
+    )))
+}
+
+/// Mark a response as carrying the bytes `start` to `end` of `len`
+pub fn set_partial(response: &mut Response, start: u64, end: u64, len: u64) {
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    response.headers_mut().insert(
+        header::CONTENT_RANGE,
+        format!("bytes {}-{}/{}", start, end, len).parse().unwrap(),
+    );
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+}
+
+/// `416 Range Not Satisfiable` for a file of `len` bytes
+pub fn unsatisfiable(len: u64) -> Response {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+    response.headers_mut().insert(
+        header::CONTENT_RANGE,
+        format!("bytes */{}", len).parse().unwrap(),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(values: &[(header::HeaderName, &str)]) -> HeaderMap {
+        values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_requested() {
+        let range = |value: &str| requested(&headers(&[(header::RANGE, value)]), 1000, None, None);
+        assert_eq!(
+            requested(&HeaderMap::new(), 1000, None, None),
+            ByteRange::Full
+        );
+        assert_eq!(range("bytes=100-200"), ByteRange::Partial(100, 200));
+        assert_eq!(range("bytes=900-"), ByteRange::Partial(900, 999));
+        assert_eq!(range("bytes=-100"), ByteRange::Partial(900, 999));
+        assert_eq!(range("bytes=-5000"), ByteRange::Partial(0, 999));
+        assert_eq!(range("bytes=990-5000"), ByteRange::Partial(990, 999));
+        assert_eq!(range("bytes=1000-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        for ignored in [
+            "bytes=0-1,5-6",
+            "bytes=200-100",
+            "items=0-1",
+            "bytes=a-b",
+            "bytes=-",
+        ] {
+            assert_eq!(range(ignored), ByteRange::Full, "{}", ignored);
+        }
+    }
+
+    #[test]
+    fn test_if_range() {
+        let etag: HeaderValue = "\"abc\"".parse().unwrap();
+        let created_at = Timestamp::from(1_700_000_000);
+        let date = crate::cache_control::last_modified(created_at);
+        let range = |if_range: &str| {
+            let headers = headers(&[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, if_range)]);
+            requested(&headers, 100, Some(&etag), Some(created_at))
+        };
+        assert_eq!(range("\"abc\""), ByteRange::Partial(0, 9));
+        assert_eq!(range("\"other\""), ByteRange::Full);
+        assert_eq!(range("W/\"abc\""), ByteRange::Full);
+        assert_eq!(range(date.to_str().unwrap()), ByteRange::Partial(0, 9));
+        assert_eq!(range("Thu, 01 Jan 1970 00:00:00 GMT"), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_partial_file_response() {
+        let path = std::env::temp_dir().join(format!("nsite-range-test-{}", std::process::id()));
+        let contents: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let len = contents.len() as u64;
+
+        let ByteRange::Partial(start, end) = requested(
+            &headers(&[(header::RANGE, "bytes=100-200")]),
+            len,
+            None,
+            None,
+        ) else {
+            panic!("a satisfiable range");
+        };
+        let mut response = Response::new(file_range(&path, start, end).await.unwrap());
+        set_partial(&mut response, start, end, len);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            "bytes 100-200/1000"
+        );
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "101");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], &contents[100..=200]);
+
+        let response = unsatisfiable(len);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */1000");
+        let _ = std::fs::remove_file(&path);
+    }
+}
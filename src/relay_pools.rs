@@ -0,0 +1,87 @@
+use anyhow::Result;
+use nostr_sdk::{Client, RelayUrl};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Nostr clients for the dedicated relay sets of aliases, one per distinct set
+///
+/// Aliases listing the same relays in any order share a client, sites without their own
+/// relays use the gateway's client and never touch these.
+#[derive(Clone, Default)]
+pub struct RelayPools {
+    clients: Arc<RwLock<HashMap<Vec<RelayUrl>, Client>>>,
+}
+
+impl RelayPools {
+    /// The client connected to exactly `relays`, created on first use
+    pub async fn client(&self, relays: &[RelayUrl]) -> Result<Client> {
+        let key = pool_relays(relays);
+        if let Some(client) = self.clients.read().await.get(&key) {
+            return Ok(client.clone());
+        }
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = Client::default();
+        for relay in &key {
+            client.add_relay(relay).await?;
+        }
+        client.connect().await;
+        log::info!("Connected dedicated relay pool {:?}", key);
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+}
+
+/// Short stable id of a relay set, keeping sites resolved from it apart in the site cache
+pub fn pool_id(relays: &[RelayUrl]) -> String {
+    let mut hasher = Sha256::new();
+    for relay in pool_relays(relays) {
+        hasher.update(relay.as_str());
+        hasher.update(b"\n");
+    }
+    hex::encode(&hasher.finalize()[..6])
+}
+
+fn pool_relays(relays: &[RelayUrl]) -> Vec<RelayUrl> {
+    let mut relays = relays.to_vec();
+    relays.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    relays.dedup();
+    relays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_id() {
+        let a = RelayUrl::parse("wss://a.example").unwrap();
+        let b = RelayUrl::parse("wss://b.example").unwrap();
+        assert_eq!(
+            pool_id(&[a.clone(), b.clone()]),
+            pool_id(&[b.clone(), a.clone(), b.clone()])
+        );
+        assert_ne!(pool_id(std::slice::from_ref(&a)), pool_id(&[b]));
+        assert_eq!(pool_id(&[a]).len(), 12);
+    }
+
+    #[tokio::test]
+    async fn test_shared_clients() {
+        let pools = RelayPools::default();
+        let a = RelayUrl::parse("ws://127.0.0.1:1").unwrap();
+        let b = RelayUrl::parse("ws://127.0.0.1:2").unwrap();
+        let first = pools.client(&[a.clone(), b.clone()]).await.unwrap();
+        let second = pools.client(&[b.clone(), a.clone()]).await.unwrap();
+        let relays: Vec<RelayUrl> = second.relays().await.into_keys().collect();
+        assert_eq!(relays.len(), 2);
+        assert_eq!(first.relays().await.len(), 2);
+        assert_eq!(pools.clients.read().await.len(), 1);
+
+        pools.client(&[a]).await.unwrap();
+        assert_eq!(pools.clients.read().await.len(), 2);
+    }
+}
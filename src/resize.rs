@@ -0,0 +1,140 @@
+use anyhow::Result;
+use image::ImageFormat;
+use image::imageops::FilterType;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Suffix counter keeping concurrent resizes of the same variant from sharing a temp file
+static PARTIAL_RESIZES: AtomicU64 = AtomicU64::new(0);
+
+/// Path of the resized variant for a cached image, keyed by hash and requested width
+fn variant_path(path: &Path, width: u32) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let ext = path.extension()?.to_str()?;
+    Some(path.with_file_name(format!("{}-w{}.{}", stem, width, ext)))
+}
+
+/// Only raster formats we can both decode and encode are resized
+fn resizable_format(path: &Path) -> Option<ImageFormat> {
+    match ImageFormat::from_path(path).ok()? {
+        f @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP) => Some(f),
+        _ => None,
+    }
+}
+
+/// Resize a cached image down to `width`, keeping the aspect ratio
+///
+/// Returns `None` when the file is not a resizable image or is already narrower than
+/// `width`, in which case the original should be served.
+/// Resized variants are cached next to the original blob.
+pub async fn resized(path: &Path, width: u32) -> Result<Option<PathBuf>> {
+    let Some(format) = resizable_format(path) else {
+        return Ok(None);
+    };
+    let Some(out_path) = variant_path(path, width) else {
+        return Ok(None);
+    };
+    if out_path.exists() {
+        return Ok(Some(out_path));
+    }
+
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        // Check the header first so images that are already small enough are never decoded
+        let (original_width, _) = image::image_dimensions(&path)?;
+        if original_width <= width {
+            return Ok(None);
+        }
+
+        let start = std::time::Instant::now();
+        let img = image::ImageReader::open(&path)?
+            .with_guessed_format()?
+            .decode()?;
+
+        // Write to a temp file of its own first so a failed encode or a concurrent resize
+        // never leaves a broken variant
+        let resized = img.resize(width, u32::MAX, FilterType::Lanczos3);
+        let mut tmp_path = out_path.as_os_str().to_owned();
+        tmp_path.push(format!(
+            ".part{}",
+            PARTIAL_RESIZES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let tmp_path = PathBuf::from(tmp_path);
+        if let Err(e) = resized
+            .save_with_format(&tmp_path, format)
+            .and_then(|_| std::fs::rename(&tmp_path, &out_path).map_err(Into::into))
+        {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        log::info!(
+            "Resized {} to {}x{} in {:?}",
+            path.display(),
+            resized.width(),
+            resized.height(),
+            start.elapsed()
+        );
+        Ok(Some(out_path))
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_variant_path() {
+        let path = PathBuf::from("/tmp/nsite/ab/abcd.png");
+        assert_eq!(
+            variant_path(&path, 400),
+            Some(PathBuf::from("/tmp/nsite/ab/abcd-w400.png"))
+        );
+        assert!(resizable_format(&path).is_some());
+        assert!(resizable_format(Path::new("/tmp/nsite/ab/abcd.svg")).is_none());
+        assert!(resizable_format(Path::new("/tmp/nsite/ab/abcd.gif")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resized() {
+        let dir = std::env::temp_dir().join("nsite-resize-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("original.png");
+        RgbImage::from_pixel(200, 100, Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+
+        let out = resized(&path, 50).await.unwrap().unwrap();
+        let img = image::open(&out).unwrap();
+        assert_eq!((img.width(), img.height()), (50, 25));
+
+        // Never upscale
+        assert!(resized(&path, 400).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_resizes() {
+        let dir = std::env::temp_dir().join("nsite-resize-concurrent-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("original.png");
+        RgbImage::from_fn(400, 200, |x, y| Rgb([x as u8, y as u8, 0]))
+            .save(&path)
+            .unwrap();
+
+        let resizes: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                tokio::spawn(async move { resized(&path, 100).await })
+            })
+            .collect();
+        for resize in resizes {
+            let out = resize.await.unwrap().unwrap().unwrap();
+            let img = image::open(&out).unwrap();
+            assert_eq!((img.width(), img.height()), (100, 50));
+        }
+        // No temp file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use lol_html::html_content::Element;
+use lol_html::{HtmlRewriter, Settings, element};
+
+/// Attributes holding a single URL
+const URL_ATTRIBUTES: [&str; 5] = ["href", "src", "action", "poster", "formaction"];
+
+/// Rewrite a link pointing at one of `origins` into a root-relative link
+///
+/// `https://original.example/a/b?c` becomes `/a/b?c`, anything else is left alone.
+fn rewrite_url(url: &str, origins: &[String]) -> Option<String> {
+    let url = url.trim();
+    origins.iter().find_map(|origin| {
+        let rest = url.strip_prefix(origin.as_str())?;
+        match rest.chars().next() {
+            None => Some("/".to_string()),
+            Some('/') => Some(rest.to_string()),
+            Some('?') | Some('#') => Some(format!("/{}", rest)),
+            // A longer host sharing the same prefix, e.g. original.example.org
+            _ => None,
+        }
+    })
+}
+
+/// Rewrite every candidate of a `srcset` attribute
+fn rewrite_srcset(srcset: &str, origins: &[String]) -> Option<String> {
+    let mut changed = false;
+    let candidates: Vec<String> = srcset
+        .split(',')
+        .map(|candidate| {
+            let candidate = candidate.trim();
+            let (url, descriptor) = candidate.split_once(' ').unwrap_or((candidate, ""));
+            match rewrite_url(url, origins) {
+                Some(url) => {
+                    changed = true;
+                    format!("{} {}", url, descriptor).trim_end().to_string()
+                }
+                None => candidate.to_string(),
+            }
+        })
+        .collect();
+    changed.then(|| candidates.join(", "))
+}
+
+fn rewrite_element(el: &mut Element, origins: &[String]) -> lol_html::HandlerResult {
+    for attr in URL_ATTRIBUTES {
+        if let Some(value) = el.get_attribute(attr)
+            && let Some(url) = rewrite_url(&value, origins)
+        {
+            el.set_attribute(attr, &url)?;
+        }
+    }
+    if let Some(value) = el.get_attribute("srcset")
+        && let Some(srcset) = rewrite_srcset(&value, origins)
+    {
+        el.set_attribute("srcset", &srcset)?;
+    }
+    Ok(())
+}
+
+/// Rewrite absolute links to any of `origins` in an HTML document so they resolve
+/// against the subdomain the site is served from
+pub fn rewrite_links(html: &[u8], origins: &[String]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(html.len());
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: vec![element!("*", |el| rewrite_element(el, origins))],
+            ..Settings::new()
+        },
+        |chunk: &[u8]| out.extend_from_slice(chunk),
+    );
+    rewriter.write(html)?;
+    rewriter.end()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins() -> Vec<String> {
+        vec!["https://original.example".to_string()]
+    }
+
+    #[test]
+    fn test_rewrite_url() {
+        let origins = origins();
+        assert_eq!(
+            rewrite_url("https://original.example", &origins).as_deref(),
+            Some("/")
+        );
+        assert_eq!(
+            rewrite_url("https://original.example/a/b.css?v=1", &origins).as_deref(),
+            Some("/a/b.css?v=1")
+        );
+        assert_eq!(
+            rewrite_url("https://original.example#top", &origins).as_deref(),
+            Some("/#top")
+        );
+        assert_eq!(
+            rewrite_url("https://original.example.org/a", &origins),
+            None
+        );
+        assert_eq!(rewrite_url("https://other.example/a", &origins), None);
+        assert_eq!(rewrite_url("/a", &origins), None);
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let html = br#"<a href="https://original.example/about">About</a><img src="https://cdn.example/x.png" srcset="https://original.example/x.png 1x, https://original.example/x2.png 2x"><p>https://original.example/text</p>"#;
+        let out = String::from_utf8(rewrite_links(html, &origins()).unwrap()).unwrap();
+        assert_eq!(
+            out,
+            r#"<a href="/about">About</a><img src="https://cdn.example/x.png" srcset="/x.png 1x, /x2.png 2x"><p>https://original.example/text</p>"#
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+use std::time::Duration;
+
+/// Content classes with their own route TTL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentClass {
+    Html,
+    Css,
+    Js,
+    Image,
+    Other,
+}
+
+impl ContentClass {
+    /// Class of a site path, paths without an extension resolve to an index.html
+    pub fn of(path: &str) -> Self {
+        if path.ends_with('/') {
+            return Self::Html;
+        }
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            None | Some("html") | Some("htm") => Self::Html,
+            Some("css") => Self::Css,
+            Some("js") | Some("mjs") => Self::Js,
+            Some("png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" | "ico") => Self::Image,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// How long a resolved route is trusted before the manifest is checked for a new version
+///
+/// Classes without a TTL of their own use the default, and without either they're only
+/// refreshed when the whole site expires.
+#[derive(Clone, Debug, Default)]
+pub struct RouteTtls {
+    pub default: Option<Duration>,
+    pub html: Option<Duration>,
+    pub css: Option<Duration>,
+    pub js: Option<Duration>,
+    pub image: Option<Duration>,
+    pub other: Option<Duration>,
+}
+
+impl RouteTtls {
+    /// TTL for a site path
+    pub fn ttl(&self, path: &str) -> Option<Duration> {
+        let ttl = match ContentClass::of(path) {
+            ContentClass::Html => self.html,
+            ContentClass::Css => self.css,
+            ContentClass::Js => self.js,
+            ContentClass::Image => self.image,
+            ContentClass::Other => self.other,
+        };
+        ttl.or(self.default)
+    }
+
+    /// Set the TTL of a class, or the default with `None`
+    pub fn set(&mut self, class: Option<ContentClass>, ttl: Duration) {
+        let slot = match class {
+            None => &mut self.default,
+            Some(ContentClass::Html) => &mut self.html,
+            Some(ContentClass::Css) => &mut self.css,
+            Some(ContentClass::Js) => &mut self.js,
+            Some(ContentClass::Image) => &mut self.image,
+            Some(ContentClass::Other) => &mut self.other,
+        };
+        *slot = Some(ttl);
+    }
+}
+
+/// Parse a `<class>=<secs>` command line TTL, e.g. `html=60`, or a bare `<secs>` default
+pub fn parse_ttl_spec(s: &str) -> Result<(Option<ContentClass>, Duration), String> {
+    let (class, secs) = match s.split_once('=') {
+        Some((class, secs)) => (Some(class), secs),
+        None => (None, s),
+    };
+    let class = match class {
+        None => None,
+        Some("html") => Some(ContentClass::Html),
+        Some("css") => Some(ContentClass::Css),
+        Some("js") => Some(ContentClass::Js),
+        Some("image") => Some(ContentClass::Image),
+        Some("other") => Some(ContentClass::Other),
+        Some(class) => {
+            return Err(format!(
+                "unknown content class '{}', expected html, css, js, image or other",
+                class
+            ));
+        }
+    };
+    let secs: u64 = secs
+        .parse()
+        .map_err(|e| format!("invalid route TTL '{}': {}", s, e))?;
+    Ok((class, Duration::from_secs(secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_class() {
+        assert_eq!(ContentClass::of("/"), ContentClass::Html);
+        assert_eq!(ContentClass::of("/about"), ContentClass::Html);
+        assert_eq!(ContentClass::of("/style.css"), ContentClass::Css);
+        assert_eq!(ContentClass::of("/app.mjs"), ContentClass::Js);
+        assert_eq!(ContentClass::of("/img/a.webp"), ContentClass::Image);
+        assert_eq!(ContentClass::of("/font.woff2"), ContentClass::Other);
+    }
+
+    #[test]
+    fn test_route_ttls() {
+        let mut ttls = RouteTtls::default();
+        let (class, ttl) = parse_ttl_spec("html=60").unwrap();
+        ttls.set(class, ttl);
+
+        assert_eq!(ttls.ttl("/index.html"), Some(Duration::from_secs(60)));
+        assert_eq!(ttls.ttl("/a.png"), None);
+
+        let (class, ttl) = parse_ttl_spec("300").unwrap();
+        assert_eq!(class, None);
+        ttls.set(class, ttl);
+        assert_eq!(ttls.ttl("/index.html"), Some(Duration::from_secs(60)));
+        assert_eq!(ttls.ttl("/a.png"), Some(Duration::from_secs(300)));
+
+        assert!(parse_ttl_spec("html").is_err());
+        assert!(parse_ttl_spec("video=10").is_err());
+        assert!(parse_ttl_spec("html=soon").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use nostr_sdk::Url;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Weight of the newest download in the smoothed success rate, in percent
+const SMOOTHING: u32 = 30;
+
+/// Download outcomes and circuit breaker state of every Blossom server the gateway has used
+pub static SERVER_HEALTH: once_cell::sync::Lazy<ServerHealth> =
+    once_cell::sync::Lazy::new(ServerHealth::default);
+
+/// When servers are skipped for failing, for `--server-breaker-failures`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breaker {
+    /// Failures within `window` that open the circuit of a server
+    pub failures: usize,
+    /// How far back failures are counted
+    pub window: Duration,
+    /// How long an open server is skipped before one download is let through to probe it
+    pub cooldown: Duration,
+}
+
+/// Circuit breaker state of one server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Circuit {
+    /// Downloads go through, failures are counted
+    Closed,
+    /// A single download probes whether the server recovered, given up on at the given time
+    HalfOpen(Instant),
+    /// The server is skipped until the given time
+    Open(Instant),
+}
+
+impl Circuit {
+    /// Value of the state in the metrics: 0 closed, 1 half-open, 2 open
+    pub fn gauge(&self) -> u8 {
+        match self {
+            Circuit::Closed => 0,
+            Circuit::HalfOpen(_) => 1,
+            Circuit::Open(_) => 2,
+        }
+    }
+}
+
+struct Health {
+    circuit: Circuit,
+    /// Recent failures while closed, oldest first
+    failures: VecDeque<Instant>,
+    /// Smoothed share of downloads that succeeded, in percent
+    success_rate: u32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            circuit: Circuit::Closed,
+            failures: VecDeque::new(),
+            success_rate: 100,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ServerHealth {
+    servers: Mutex<HashMap<String, Health>>,
+}
+
+impl ServerHealth {
+    /// Record whether a server served a blob asked from it
+    ///
+    /// A success closes the circuit. A failed probe opens it again for another cooldown,
+    /// and reaching `breaker.failures` within the window while closed opens it.
+    pub fn record(&self, server: &Url, ok: bool, breaker: Option<&Breaker>, now: Instant) {
+        let mut servers = self.servers.lock().unwrap();
+        let health = servers.entry(server.to_string()).or_default();
+        let sample = if ok { 100 } else { 0 };
+        health.success_rate = (health.success_rate * (100 - SMOOTHING) + sample * SMOOTHING) / 100;
+        if ok {
+            health.circuit = Circuit::Closed;
+            health.failures.clear();
+            return;
+        }
+        let Some(breaker) = breaker else {
+            return;
+        };
+        match health.circuit {
+            Circuit::HalfOpen(_) => {
+                log::info!(
+                    "{} still failing, skipping it for another {:?}",
+                    server,
+                    breaker.cooldown
+                );
+                health.circuit = Circuit::Open(now + breaker.cooldown);
+            }
+            Circuit::Open(_) => {}
+            Circuit::Closed => {
+                health.failures.push_back(now);
+                while health
+                    .failures
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) > breaker.window)
+                {
+                    health.failures.pop_front();
+                }
+                if health.failures.len() >= breaker.failures {
+                    log::warn!(
+                        "{} failed {} times within {:?}, skipping it for {:?}",
+                        server,
+                        health.failures.len(),
+                        breaker.window,
+                        breaker.cooldown
+                    );
+                    health.failures.clear();
+                    health.circuit = Circuit::Open(now + breaker.cooldown);
+                }
+            }
+        }
+    }
+
+    /// Whether a download may ask `server`, letting a single probe through once an open
+    /// circuit cooled down
+    ///
+    /// A probe that never reports back, because an earlier server served the blob, is
+    /// given up on after another cooldown and the next download probes again.
+    pub fn allow(&self, server: &Url, breaker: &Breaker, now: Instant) -> bool {
+        let mut servers = self.servers.lock().unwrap();
+        let Some(health) = servers.get_mut(server.as_str()) else {
+            return true;
+        };
+        match health.circuit {
+            Circuit::Closed => true,
+            Circuit::Open(until) | Circuit::HalfOpen(until) if now < until => false,
+            Circuit::Open(_) | Circuit::HalfOpen(_) => {
+                health.circuit = Circuit::HalfOpen(now + breaker.cooldown);
+                true
+            }
+        }
+    }
+
+    /// Smoothed share of recent downloads `server` served, in percent, 100 for servers
+    /// not used yet
+    pub fn success_rate(&self, server: &Url) -> u32 {
+        let servers = self.servers.lock().unwrap();
+        servers.get(server.as_str()).map_or(100, |h| h.success_rate)
+    }
+
+    /// Circuit state and success rate of every server used so far, by server
+    pub fn states(&self) -> BTreeMap<String, (Circuit, u32)> {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .iter()
+            .map(|(server, h)| (server.clone(), (h.circuit, h.success_rate)))
+            .collect()
+    }
+
+    /// Circuit states and success rates in the Prometheus text format, for `/metrics`
+    pub fn render(&self) -> String {
+        let states = self.states();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP nsite_blossom_circuit_state Circuit breaker of each Blossom server, 0 closed, 1 half-open, 2 open"
+        );
+        let _ = writeln!(out, "# TYPE nsite_blossom_circuit_state gauge");
+        for (server, (circuit, _)) in &states {
+            let server = crate::metrics::escape_label(server);
+            let _ = writeln!(
+                out,
+                "nsite_blossom_circuit_state{{server=\"{}\"}} {}",
+                server,
+                circuit.gauge()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP nsite_blossom_success_ratio Smoothed share of recent downloads each Blossom server served"
+        );
+        let _ = writeln!(out, "# TYPE nsite_blossom_success_ratio gauge");
+        for (server, (_, rate)) in &states {
+            let server = crate::metrics::escape_label(server);
+            let _ = writeln!(
+                out,
+                "nsite_blossom_success_ratio{{server=\"{}\"}} {}",
+                server,
+                *rate as f64 / 100.0
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREAKER: Breaker = Breaker {
+        failures: 3,
+        window: Duration::from_secs(60),
+        cooldown: Duration::from_secs(30),
+    };
+
+    fn circuit(health: &ServerHealth, server: &Url) -> Circuit {
+        health.states()[server.as_str()].0
+    }
+
+    #[test]
+    fn test_circuit_transitions() {
+        let health = ServerHealth::default();
+        let server: Url = "https://a.example/".parse().unwrap();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // Failures spread wider than the window don't open the circuit
+        health.record(&server, false, Some(&BREAKER), at(0));
+        health.record(&server, false, Some(&BREAKER), at(50));
+        health.record(&server, false, Some(&BREAKER), at(100));
+        assert_eq!(circuit(&health, &server), Circuit::Closed);
+        assert!(health.allow(&server, &BREAKER, at(100)));
+
+        health.record(&server, false, Some(&BREAKER), at(110));
+        assert_eq!(circuit(&health, &server), Circuit::Open(at(140)));
+        assert!(!health.allow(&server, &BREAKER, at(139)));
+
+        // One probe goes through after the cooldown, a failed probe opens it again
+        assert!(health.allow(&server, &BREAKER, at(140)));
+        assert_eq!(circuit(&health, &server), Circuit::HalfOpen(at(170)));
+        assert!(!health.allow(&server, &BREAKER, at(141)));
+        health.record(&server, false, Some(&BREAKER), at(142));
+        assert_eq!(circuit(&health, &server), Circuit::Open(at(172)));
+
+        // A successful probe closes it
+        assert!(health.allow(&server, &BREAKER, at(172)));
+        health.record(&server, true, Some(&BREAKER), at(173));
+        assert_eq!(circuit(&health, &server), Circuit::Closed);
+        assert!(health.allow(&server, &BREAKER, at(173)));
+    }
+
+    #[test]
+    fn test_abandoned_probe() {
+        let health = ServerHealth::default();
+        let server: Url = "https://a.example/".parse().unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            health.record(&server, false, Some(&BREAKER), start);
+        }
+        let after = |secs: u64| start + Duration::from_secs(secs);
+        assert!(health.allow(&server, &BREAKER, after(30)));
+        assert!(!health.allow(&server, &BREAKER, after(45)));
+        assert!(health.allow(&server, &BREAKER, after(60)));
+    }
+
+    #[test]
+    fn test_success_rate() {
+        let health = ServerHealth::default();
+        let server: Url = "https://a.example/".parse().unwrap();
+        assert_eq!(health.success_rate(&server), 100);
+        // Without a breaker failures only lower the success rate
+        for _ in 0..5 {
+            health.record(&server, false, None, Instant::now());
+        }
+        assert_eq!(circuit(&health, &server), Circuit::Closed);
+        assert_eq!(health.success_rate(&server), 16);
+        health.record(&server, true, None, Instant::now());
+        assert_eq!(health.success_rate(&server), 41);
+
+        for _ in 0..3 {
+            health.record(&server, false, Some(&BREAKER), Instant::now());
+        }
+        let out = health.render();
+        assert!(out.contains("nsite_blossom_circuit_state{server=\"https://a.example/\"} 2"));
+        assert!(out.contains("nsite_blossom_success_ratio{server=\"https://a.example/\"} 0.13"));
+    }
+}
@@ -0,0 +1,148 @@
+use nostr_sdk::Url;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Latency charged to a server that failed to serve a blob
+const FAILURE_PENALTY: Duration = Duration::from_secs(10);
+
+/// Weight of the newest sample in the smoothed latency, in percent
+const SMOOTHING: u32 = 30;
+
+/// Download latencies of every Blossom server the gateway has used
+pub static SERVER_STATS: once_cell::sync::Lazy<ServerStats> =
+    once_cell::sync::Lazy::new(ServerStats::default);
+
+/// Order in which a site's Blossom servers are tried for a blob
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(clap::ValueEnum))]
+pub enum ServerOrdering {
+    /// The order the site publishes its servers in
+    #[default]
+    Owner,
+    /// Lowest measured latency first, servers not measured yet are tried before the rest
+    Fastest,
+    /// Rotate the starting server on every download to spread load
+    RoundRobin,
+    /// Highest recent share of successful downloads first, servers not used yet count as
+    /// fully reliable
+    Reliable,
+    /// Ask every server at once and use the first to serve the blob, trading upstream
+    /// requests for tail latency when a server is slow or down
+    Race,
+}
+
+#[derive(Default)]
+pub struct ServerStats {
+    latencies: Mutex<HashMap<String, Duration>>,
+    next: AtomicUsize,
+}
+
+impl ServerStats {
+    /// Record how long a server took to start serving a blob, or that it failed to
+    pub fn record(&self, server: &Url, elapsed: Option<Duration>) {
+        let sample = elapsed.unwrap_or(FAILURE_PENALTY);
+        let mut latencies = self.latencies.lock().unwrap();
+        latencies
+            .entry(server.to_string())
+            .and_modify(|l| *l = (*l * (100 - SMOOTHING) + sample * SMOOTHING) / 100)
+            .or_insert(sample);
+    }
+
+    /// Servers in the order a download should try them
+    pub fn order(&self, servers: &[Url], ordering: ServerOrdering) -> Vec<Url> {
+        let mut ordered = servers.to_vec();
+        match ordering {
+            ServerOrdering::Owner | ServerOrdering::Race => {}
+            ServerOrdering::Fastest => {
+                let latencies = self.latencies.lock().unwrap();
+                ordered.sort_by_key(|s| latencies.get(s.as_str()).copied().unwrap_or_default());
+            }
+            ServerOrdering::RoundRobin if !ordered.is_empty() => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % ordered.len();
+                ordered.rotate_left(start);
+            }
+            ServerOrdering::RoundRobin => {}
+            ServerOrdering::Reliable => {
+                let health = &crate::server_health::SERVER_HEALTH;
+                ordered.sort_by_key(|s| std::cmp::Reverse(health.success_rate(s)));
+            }
+        }
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Vec<Url> {
+        [
+            "https://a.example/",
+            "https://b.example/",
+            "https://c.example/",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_owner_and_round_robin() {
+        let stats = ServerStats::default();
+        let servers = servers();
+        assert_eq!(stats.order(&servers, ServerOrdering::Owner), servers);
+
+        let first: Vec<Url> = (0..3)
+            .map(|_| stats.order(&servers, ServerOrdering::RoundRobin)[0].clone())
+            .collect();
+        assert_eq!(first, servers);
+        assert!(stats.order(&[], ServerOrdering::RoundRobin).is_empty());
+    }
+
+    #[test]
+    fn test_fastest() {
+        let stats = ServerStats::default();
+        let servers = servers();
+        stats.record(&servers[0], None);
+        stats.record(&servers[1], Some(Duration::from_millis(200)));
+        stats.record(&servers[1], Some(Duration::from_millis(100)));
+
+        // c has no samples yet so it's tried first, a failed so it goes last
+        let ordered = stats.order(&servers, ServerOrdering::Fastest);
+        assert_eq!(
+            ordered,
+            vec![servers[2].clone(), servers[1].clone(), servers[0].clone()]
+        );
+        assert_eq!(
+            stats.latencies.lock().unwrap()[servers[1].as_str()],
+            Duration::from_millis(170)
+        );
+    }
+
+    #[test]
+    fn test_reliable() {
+        let stats = ServerStats::default();
+        let servers: Vec<Url> = [
+            "https://reliable-a.example/",
+            "https://reliable-b.example/",
+            "https://reliable-c.example/",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let health = &crate::server_health::SERVER_HEALTH;
+        let now = std::time::Instant::now();
+        health.record(&servers[0], false, None, now);
+        health.record(&servers[0], false, None, now);
+        health.record(&servers[1], false, None, now);
+
+        // Stable for equal rates, so unused servers keep the owner's order
+        let ordered = stats.order(&servers, ServerOrdering::Reliable);
+        assert_eq!(
+            ordered,
+            vec![servers[2].clone(), servers[1].clone(), servers[0].clone()]
+        );
+    }
+}
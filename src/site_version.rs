@@ -0,0 +1,51 @@
+use nostr_sdk::{Event, TagKind};
+use std::borrow::Cow;
+
+/// Version a site manifest declares with its first `["version", "<n>"]` tag
+///
+/// Versions are whole numbers the owner bumps on every deploy that clients should notice,
+/// a tag that isn't one counts as no version.
+pub fn declared(manifest: &Event) -> Option<u64> {
+    manifest
+        .tags
+        .find(TagKind::Custom(Cow::Borrowed("version")))
+        .and_then(|tag| tag.content())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Whether a site at `version` is older than the `min` version a client asks for, sites
+/// without a version are older than any
+pub fn is_older(version: Option<u64>, min: u64) -> bool {
+    version.is_none_or(|v| v < min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    fn manifest(tags: &[&[&str]]) -> Event {
+        EventBuilder::new(Kind::Custom(15128), "")
+            .tags(tags.iter().map(|t| Tag::parse(t.to_vec()).unwrap()))
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_declared() {
+        assert_eq!(
+            declared(&manifest(&[&["version", "42"], &["version", "43"]])),
+            Some(42)
+        );
+        assert_eq!(declared(&manifest(&[&["version", "v2"]])), None);
+        assert_eq!(declared(&manifest(&[&["path", "/index.html", "00"]])), None);
+    }
+
+    #[test]
+    fn test_is_older() {
+        assert!(is_older(Some(1), 2));
+        assert!(!is_older(Some(2), 2));
+        assert!(!is_older(Some(3), 2));
+        assert!(is_older(None, 1));
+    }
+}
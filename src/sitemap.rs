@@ -0,0 +1,68 @@
+use nostr_sdk::Timestamp;
+use std::fmt::Write;
+
+/// Map a published file path to the URL path of the page it serves
+/// Only HTML files are pages, `index.html` is served at its directory
+fn page_path(path: &str) -> Option<&str> {
+    if let Some(dir) = path.strip_suffix("index.html") {
+        Some(dir)
+    } else if path.ends_with(".html") || path.ends_with(".htm") {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Generate a sitemap.xml listing every HTML page of a site
+/// `base_url` is the site origin without a trailing slash, e.g. `https://npub1....example.com`
+pub fn sitemap_xml(base_url: &str, paths: &[String], lastmod: Timestamp) -> String {
+    let mut pages: Vec<&str> = paths.iter().filter_map(|p| page_path(p)).collect();
+    pages.sort_unstable();
+    pages.dedup();
+
+    let lastmod = lastmod.to_human_datetime();
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        let _ = write!(
+            xml,
+            "  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>\n",
+            escape_xml(&format!("{}{}", base_url, page)),
+            lastmod
+        );
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sitemap_xml() {
+        let paths = vec![
+            "/index.html".to_string(),
+            "/style.css".to_string(),
+            "/blog/index.html".to_string(),
+            "/blog/a&b.html".to_string(),
+        ];
+        let xml = sitemap_xml("https://example.com", &paths, Timestamp::from(0));
+
+        assert!(xml.contains("<loc>https://example.com/</loc>"));
+        assert!(xml.contains("<loc>https://example.com/blog/</loc>"));
+        assert!(xml.contains("<loc>https://example.com/blog/a&amp;b.html</loc>"));
+        assert!(!xml.contains("style.css"));
+        assert!(xml.contains("<lastmod>1970-01-01T00:00:00Z</lastmod>"));
+    }
+}
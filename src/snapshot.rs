@@ -0,0 +1,178 @@
+use crate::site::{RouteContent, SiteConfig, SiteInfo};
+use anyhow::{Context, Result, anyhow, bail};
+use futures::StreamExt;
+use log::info;
+use nostr_sdk::prelude::{Events, MatchEventOptions};
+use nostr_sdk::{Client, Event, Filter, PublicKey};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File in a snapshot directory holding the site manifest events
+const MANIFESTS_FILE: &str = "manifests.json";
+
+/// A read-only bundle of site manifests and the blobs they publish
+///
+/// Blobs use the same `<xx>/<hash>.<ext>` layout as the disk cache, so a snapshot
+/// directory can be served without copying anything.
+pub struct Snapshot {
+    pub dir: PathBuf,
+    manifests: Vec<Event>,
+}
+
+impl Snapshot {
+    /// Load a snapshot directory, every manifest must carry a valid signature
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFESTS_FILE);
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Failed to read snapshot manifests {}", path.display()))?;
+        let manifests: Vec<Event> = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid snapshot manifests {}", path.display()))?;
+        for event in &manifests {
+            event
+                .verify()
+                .with_context(|| format!("Invalid signature on snapshot manifest {}", event.id))?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifests,
+        })
+    }
+
+    /// Manifests in the snapshot matching a filter, answered like a relay would
+    pub fn events(&self, filter: &Filter) -> Events {
+        let mut events = Events::new(filter);
+        for event in &self.manifests {
+            if filter.match_event(event, MatchEventOptions::default()) {
+                events.insert(event.clone());
+            }
+        }
+        events
+    }
+}
+
+/// Write sites and every blob they publish to a snapshot directory
+///
+/// Sites are given as `<npub or hex pubkey>[:<identifier>]`.
+pub async fn export(
+    client: &Client,
+    config: &Arc<SiteConfig>,
+    sites: &[String],
+    dir: &Path,
+) -> Result<()> {
+    let mut manifests = Vec::new();
+    for spec in sites {
+        let (pubkey, identifier) = parse_site_spec(spec)?;
+        let site = SiteInfo::load(client, config, &pubkey, identifier.as_deref(), None)
+            .await?
+            .ok_or_else(|| anyhow!("No site found for {}", spec))?;
+        let manifest = site
+            .manifest()
+            .await?
+            .ok_or_else(|| anyhow!("No manifest found for {}", spec))?;
+        let routes = site.list("/").await?.unwrap_or_default();
+        for route in &routes {
+            let out_path = route.blob_path(dir);
+            if out_path.exists() {
+                continue;
+            }
+            if let Some(out_dir) = out_path.parent() {
+                tokio::fs::create_dir_all(out_dir).await?;
+            }
+            match site.serve_route(&route.path).await? {
+                RouteContent::Cached(path) => {
+                    tokio::fs::copy(&path, &out_path).await?;
+                }
+                RouteContent::Stream { mut body, .. } => {
+                    let mut contents = Vec::new();
+                    while let Some(chunk) = body.next().await {
+                        contents.extend_from_slice(&chunk?);
+                    }
+                    tokio::fs::write(&out_path, contents).await?;
+                }
+            }
+        }
+        info!("Exported {} with {} routes", spec, routes.len());
+        manifests.push(manifest);
+    }
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(
+        dir.join(MANIFESTS_FILE),
+        serde_json::to_vec_pretty(&manifests)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Parse a `<npub or hex pubkey>[:<identifier>]` site
+fn parse_site_spec(spec: &str) -> Result<([u8; 32], Option<String>)> {
+    let (key, identifier) = match spec.split_once(':') {
+        Some((key, id)) if !id.is_empty() => (key, Some(id.to_string())),
+        Some(_) => bail!("Empty identifier in site '{}'", spec),
+        None => (spec, None),
+    };
+    let pubkey =
+        PublicKey::parse(key).map_err(|e| anyhow!("Invalid pubkey in site '{}': {}", spec, e))?;
+    Ok((pubkey.to_bytes(), identifier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag, ToBech32};
+
+    #[test]
+    fn test_parse_site_spec() {
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let hex = keys.public_key().to_hex();
+
+        assert_eq!(
+            parse_site_spec(&npub).unwrap(),
+            (keys.public_key().to_bytes(), None)
+        );
+        assert_eq!(
+            parse_site_spec(&format!("{}:blog", hex)).unwrap(),
+            (keys.public_key().to_bytes(), Some("blog".to_string()))
+        );
+        assert!(parse_site_spec(&format!("{}:", npub)).is_err());
+        assert!(parse_site_spec("npub1nope").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_events() {
+        let dir = std::env::temp_dir().join("nsite-snapshot-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let keys = Keys::generate();
+        let manifest = EventBuilder::new(Kind::Custom(crate::site::ROOT_SITE_KIND), "")
+            .tags([Tag::parse(["path", "/index.html", &hex::encode([1u8; 32])]).unwrap()])
+            .sign_with_keys(&keys)
+            .unwrap();
+        std::fs::write(
+            dir.join(MANIFESTS_FILE),
+            serde_json::to_vec(&[&manifest]).unwrap(),
+        )
+        .unwrap();
+
+        let snapshot = Snapshot::load(&dir).unwrap();
+        let filter = Filter::new()
+            .kind(Kind::Custom(crate::site::ROOT_SITE_KIND))
+            .author(keys.public_key());
+        assert_eq!(
+            snapshot.events(&filter).first().map(|e| e.id),
+            Some(manifest.id)
+        );
+
+        let other = Filter::new().author(Keys::generate().public_key());
+        assert!(snapshot.events(&other).is_empty());
+
+        let mut tampered = serde_json::to_value(&manifest).unwrap();
+        tampered["content"] = "changed".into();
+        std::fs::write(
+            dir.join(MANIFESTS_FILE),
+            serde_json::to_vec(&[tampered]).unwrap(),
+        )
+        .unwrap();
+        assert!(Snapshot::load(&dir).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+/// How many leading bytes of a file are looked at to guess its content type
+pub const SNIFF_LEN: usize = 512;
+
+/// Guess a content type from the first bytes of a file, `None` when nothing matches
+///
+/// Binary formats are recognised by their magic bytes, HTML and SVG by their opening
+/// markup after any byte order mark and whitespace.
+pub fn content_type(head: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"\x00asm", "application/wasm"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, content_type)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(content_type);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(match &head[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            _ => "video/mp4",
+        });
+    }
+    markup_content_type(head)
+}
+
+fn markup_content_type(head: &[u8]) -> Option<&'static str> {
+    let text = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace())?;
+    let text = String::from_utf8_lossy(&text[start..]).to_ascii_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        Some("text/html")
+    } else if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+/// Content type for a file extension the gateway knows
+pub fn extension_content_type(file_path: &Path) -> Option<&'static str> {
+    let content_type = match file_path.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magic_bytes() {
+        assert_eq!(
+            content_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(
+            content_type(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+            Some("image/jpeg")
+        );
+        assert_eq!(content_type(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(
+            content_type(b"\0\0\0\x1cftypavif\0\0\0\0"),
+            Some("image/avif")
+        );
+        assert_eq!(
+            content_type(b"\0\0\0\x18ftypmp42\0\0\0\0"),
+            Some("video/mp4")
+        );
+        assert_eq!(content_type(b"wOF2\0\x01\0\0"), Some("font/woff2"));
+        assert_eq!(content_type(b"\0asm\x01\0\0\0"), Some("application/wasm"));
+    }
+
+    #[test]
+    fn test_markup() {
+        assert_eq!(
+            content_type(b"\xef\xbb\xbf\n  <!DOCTYPE html><html>"),
+            Some("text/html")
+        );
+        assert_eq!(content_type(b"<html lang=\"en\">"), Some("text/html"));
+        assert_eq!(
+            content_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(
+            content_type(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(content_type(b"<?xml version=\"1.0\"?><rss/>"), None);
+    }
+
+    #[test]
+    fn test_inconclusive() {
+        assert_eq!(content_type(b""), None);
+        assert_eq!(content_type(b"   "), None);
+        assert_eq!(content_type(b"console.log('hi')"), None);
+        assert_eq!(content_type(b"RIFF"), None);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use lol_html::html_content::Element;
+use lol_html::{HtmlRewriter, Settings, element};
+use std::collections::HashMap;
+
+/// `link` relations whose target is fetched with integrity checks, for stylesheets and
+/// preloaded scripts
+const INTEGRITY_RELS: [&str; 3] = ["stylesheet", "modulepreload", "preload"];
+
+/// Subresource Integrity value of a blob, `sha256-` and its base64 hash
+pub fn integrity(hash: &[u8; 32]) -> String {
+    format!("sha256-{}", base64(hash))
+}
+
+/// Add `integrity` attributes to the scripts and stylesheets of an HTML page whose site
+/// path has a known hash in `hashes`
+///
+/// References are resolved against `page_path` like [`crate::prefetch::asset_paths`] does,
+/// external URLs and elements that already declare an integrity are left alone.
+pub fn inject_integrity(
+    html: &[u8],
+    page_path: &str,
+    hashes: &HashMap<String, [u8; 32]>,
+) -> Result<Vec<u8>> {
+    let set = |el: &mut Element, attr: &str| -> lol_html::HandlerResult {
+        if el.has_attribute("integrity") {
+            return Ok(());
+        }
+        if let Some(hash) = el
+            .get_attribute(attr)
+            .and_then(|v| crate::prefetch::resolve(&v, page_path))
+            .and_then(|path| hashes.get(&path))
+        {
+            el.set_attribute("integrity", &integrity(hash))?;
+        }
+        Ok(())
+    };
+    let mut out = Vec::with_capacity(html.len());
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: vec![
+                element!("script[src]", |el| set(el, "src")),
+                element!("link[href]", |el| {
+                    let rel = el
+                        .get_attribute("rel")
+                        .unwrap_or_default()
+                        .to_ascii_lowercase();
+                    let checked = el.get_attribute("as").is_none_or(|a| {
+                        a.eq_ignore_ascii_case("script") || a.eq_ignore_ascii_case("style")
+                    });
+                    match rel.as_str() {
+                        "preload" if !checked => Ok(()),
+                        rel if INTEGRITY_RELS.contains(&rel) => set(el, "href"),
+                        _ => Ok(()),
+                    }
+                }),
+            ],
+            ..Settings::new()
+        },
+        |chunk: &[u8]| out.extend_from_slice(chunk),
+    );
+    rewriter.write(html)?;
+    rewriter.end()?;
+    Ok(out)
+}
+
+/// Standard padded base64
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_inject_integrity() {
+        let hashes = HashMap::from([
+            ("/assets/app.js".to_string(), [1u8; 32]),
+            ("/style.css".to_string(), [2u8; 32]),
+        ]);
+        let html = br#"<script src="assets/app.js?v=2"></script><link rel="stylesheet" href="/style.css"><script src="/other.js"></script><script src="https://cdn.example/app.js"></script><link rel="preload" as="image" href="/style.css"><link rel="stylesheet" href="/style.css" integrity="sha384-x">"#;
+        let out =
+            String::from_utf8(inject_integrity(html, "/index.html", &hashes).unwrap()).unwrap();
+        let (one, two) = (integrity(&[1u8; 32]), integrity(&[2u8; 32]));
+        assert_eq!(
+            out,
+            format!(
+                r#"<script src="assets/app.js?v=2" integrity="{}"></script><link rel="stylesheet" href="/style.css" integrity="{}"><script src="/other.js"></script><script src="https://cdn.example/app.js"></script><link rel="preload" as="image" href="/style.css"><link rel="stylesheet" href="/style.css" integrity="sha384-x">"#,
+                one, two
+            )
+        );
+        assert_eq!(one, "sha256-AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=");
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{Context, Result, anyhow};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use log::{debug, info, warn};
+use rustls::ServerConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+/// Time allowed for a client to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time open connections get to finish after shutdown is requested
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// What the root domain answers to requests over plain HTTP
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ApexHttp {
+    /// Serve it like HTTPS
+    #[default]
+    Serve,
+    /// Redirect pages to HTTPS and refuse internal endpoints with 403
+    Redirect,
+}
+
+/// Extension marking a request that arrived over HTTPS, at [`serve`] or, with
+/// `--trust-forwarded-proto`, through a proxy that terminated TLS
+#[derive(Clone, Copy, Debug)]
+pub struct Https;
+
+/// Whether `request` arrived over HTTPS, a client's own `X-Forwarded-Proto` doesn't count
+pub fn is_https<B>(request: &axum::http::Request<B>) -> bool {
+    request.extensions().get::<Https>().is_some()
+}
+
+/// Take `X-Forwarded-Proto: https` from the proxy in front as arriving over HTTPS, for
+/// `--trust-forwarded-proto`
+pub async fn trust_forwarded_proto(mut request: Request, next: Next) -> Response {
+    let forwarded = request
+        .headers()
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok());
+    if forwarded == Some("https") {
+        request.extensions_mut().insert(Https);
+    }
+    next.run(request).await
+}
+
+/// HTTPS URL of `path_and_query` on `host`, on `port` unless it's the default 443
+///
+/// A port in `host` is replaced, it belongs to the plain HTTP listener.
+pub fn https_location(host: &str, port: Option<u16>, path_and_query: &str) -> String {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    match port {
+        Some(port) if port != 443 => format!("https://{}:{}{}", name, port, path_and_query),
+        _ => format!("https://{}{}", name, path_and_query),
+    }
+}
+
+/// Load a PEM certificate chain and private key into a server config
+pub fn load_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Serve `app` over TLS until `shutdown` resolves
+///
+/// Requests are marked with [`Https`] so the app builds https URLs.
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+    let graceful = GracefulShutdown::new();
+    let builder = Builder::new(TokioExecutor::new());
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(a) => a,
+                Err(e) => {
+                    warn!("Failed to accept TLS connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(s)) => s,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", addr, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", addr);
+                        return;
+                    }
+                };
+            let service = app.map_request(move |req: hyper::Request<Incoming>| {
+                let mut req = req.map(Body::new);
+                req.extensions_mut().insert(Https);
+                req.extensions_mut().insert(ConnectInfo(addr));
+                req
+            });
+            let conn = builder.serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(service),
+            );
+            if let Err(e) = watcher.watch(conn.into_owned()).await {
+                debug!("TLS connection from {} closed: {}", addr, e);
+            }
+        });
+    }
+
+    info!("Waiting for TLS connections to close");
+    if tokio::time::timeout(SHUTDOWN_GRACE, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("TLS connections still open after {:?}", SHUTDOWN_GRACE);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config_invalid() {
+        let dir = std::env::temp_dir().join("nsite-tls-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "").unwrap();
+
+        assert!(load_config(&dir.join("missing.pem"), &empty).is_err());
+        assert!(load_config(&empty, &empty).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_proto() {
+        use axum::routing::get;
+
+        let handler =
+            get(
+                |request: Request| async move { if is_https(&request) { "https" } else { "http" } },
+            );
+        let scheme = |app: Router| async move {
+            let request = Request::get("/metrics")
+                .header("x-forwarded-proto", "https")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        // A client on the plain listener can't claim HTTPS
+        let plain = Router::new().fallback(handler.clone());
+        assert_eq!(scheme(plain).await, "http");
+        let proxied = Router::new()
+            .fallback(handler)
+            .layer(axum::middleware::from_fn(trust_forwarded_proto));
+        assert_eq!(scheme(proxied).await, "https");
+    }
+
+    #[test]
+    fn test_https_location() {
+        assert_eq!(
+            https_location("example.com", None, "/"),
+            "https://example.com/"
+        );
+        assert_eq!(
+            https_location("example.com:80", Some(443), "/a?b=1"),
+            "https://example.com/a?b=1"
+        );
+        assert_eq!(
+            https_location("example.com:3000", Some(8443), "/"),
+            "https://example.com:8443/"
+        );
+        assert_eq!(
+            https_location("[::1]:3000", Some(8443), "/"),
+            "https://[::1]:8443/"
+        );
+        assert_eq!(https_location("[::1]", None, "/"), "https://[::1]/");
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window over which new certificate approvals are counted
+const APPROVAL_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Site lookups allowed in a window for each approval, so hostnames without a site can't
+/// make the gateway query relays without bound
+const LOOKUPS_PER_APPROVAL: u32 = 4;
+
+/// Maximum number of approved hostnames remembered, beyond that they count against the limit again
+const MAX_APPROVED: usize = 100_000;
+
+/// Certificate approvals handed out to an ACME client asking whether to provision a hostname
+pub static TLS_APPROVALS: once_cell::sync::Lazy<Mutex<Approvals>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(Approvals::default()));
+
+#[derive(Default)]
+pub struct Approvals {
+    /// Hostnames already approved, asking again doesn't count as a new certificate
+    approved: HashSet<String>,
+    window_start: Option<Instant>,
+    window_count: u32,
+    /// Sites looked up for hostnames not approved yet in the current window
+    window_lookups: u32,
+}
+
+/// Whether a hostname asked about may be looked up, from [`Approvals::admit`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Approved before, no need to resolve its site again
+    Approved,
+    /// Resolve its site, then [`Approvals::approve`] it
+    Lookup,
+    /// The hourly limit is reached, refuse without looking anything up
+    Limited,
+}
+
+impl Approvals {
+    /// Decide whether the site of `hostname` may be resolved, before any relay is asked
+    ///
+    /// Refused once `max_per_window` new hostnames were approved in the current window,
+    /// or once it saw a few times that many lookups.
+    pub fn admit(&mut self, hostname: &str, max_per_window: u32, now: Instant) -> Admission {
+        if self.approved.contains(hostname) {
+            return Admission::Approved;
+        }
+        self.roll_window(now);
+        if self.window_count >= max_per_window
+            || self.window_lookups >= max_per_window.saturating_mul(LOOKUPS_PER_APPROVAL)
+        {
+            return Admission::Limited;
+        }
+        self.window_lookups += 1;
+        Admission::Lookup
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= APPROVAL_WINDOW)
+        {
+            self.window_start = Some(now);
+            self.window_count = 0;
+            self.window_lookups = 0;
+        }
+    }
+
+    /// Approve a hostname whose site resolved, unless `max_per_window` new hostnames
+    /// were already approved in the current window
+    pub fn approve(&mut self, hostname: &str, max_per_window: u32, now: Instant) -> bool {
+        if self.approved.contains(hostname) {
+            return true;
+        }
+        self.roll_window(now);
+        if self.window_count >= max_per_window {
+            return false;
+        }
+        if self.approved.len() >= MAX_APPROVED {
+            self.approved.clear();
+        }
+        self.window_count += 1;
+        self.approved.insert(hostname.to_string());
+        true
+    }
+}
+
+/// Check that `hostname` is a direct subdomain of one of the allowed base domains, or a
+/// hex pubkey split into two labels below one
+pub fn is_allowed_hostname(hostname: &str, allowed_domains: &[String]) -> bool {
+    allowed_domains.iter().any(|domain| {
+        hostname
+            .strip_suffix(domain.as_str())
+            .and_then(|labels| labels.strip_suffix('.'))
+            .is_some_and(|labels| match labels.split_once('.') {
+                None => !labels.is_empty(),
+                Some((a, b)) => [a, b]
+                    .iter()
+                    .all(|l| l.len() == 32 && l.bytes().all(|c| c.is_ascii_hexdigit())),
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed_hostname() {
+        let allowed = vec!["example.com".to_string()];
+        assert!(is_allowed_hostname("npub1abc.example.com", &allowed));
+        assert!(!is_allowed_hostname("example.com", &allowed));
+        assert!(!is_allowed_hostname("a.b.example.com", &allowed));
+        let hex = "ab".repeat(32);
+        let split = format!("{}.{}.example.com", &hex[..32], &hex[32..]);
+        assert!(is_allowed_hostname(&split, &allowed));
+        let deeper = format!("a.{}.{}.example.com", &hex[..32], &hex[32..]);
+        assert!(!is_allowed_hostname(&deeper, &allowed));
+        assert!(!is_allowed_hostname("npub1abc.badexample.com", &allowed));
+        assert!(!is_allowed_hostname(
+            "npub1abc.example.com.evil.net",
+            &allowed
+        ));
+    }
+
+    #[test]
+    fn test_approvals_rate_limited() {
+        let mut approvals = Approvals::default();
+        let now = Instant::now();
+        assert!(approvals.approve("a.example.com", 2, now));
+        assert!(approvals.approve("b.example.com", 2, now));
+        assert!(!approvals.approve("c.example.com", 2, now));
+
+        // Already approved hostnames don't count against the limit
+        assert!(approvals.approve("a.example.com", 2, now));
+
+        // A new window allows new approvals again
+        assert!(approvals.approve("c.example.com", 2, now + APPROVAL_WINDOW));
+    }
+
+    #[test]
+    fn test_admit_before_lookup() {
+        let mut approvals = Approvals::default();
+        let now = Instant::now();
+        assert_eq!(approvals.admit("a.example.com", 1, now), Admission::Lookup);
+        assert!(approvals.approve("a.example.com", 1, now));
+        assert_eq!(
+            approvals.admit("a.example.com", 1, now),
+            Admission::Approved
+        );
+        // The limit is reached, nothing else is looked up
+        assert_eq!(approvals.admit("b.example.com", 1, now), Admission::Limited);
+
+        // Hostnames without a site use up the lookups
+        let mut approvals = Approvals::default();
+        for i in 0..LOOKUPS_PER_APPROVAL * 2 {
+            let hostname = format!("missing{}.example.com", i);
+            assert_eq!(approvals.admit(&hostname, 2, now), Admission::Lookup);
+        }
+        assert_eq!(approvals.admit("c.example.com", 2, now), Admission::Limited);
+        let later = now + APPROVAL_WINDOW;
+        assert_eq!(
+            approvals.admit("c.example.com", 2, later),
+            Admission::Lookup
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use nostr_sdk::{Event, TagKind, Timestamp};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Most removed paths remembered for a site, further removals are forgotten
+const MAX_PATHS: usize = 1024;
+
+/// Most sites tracked, the one with the oldest manifest is dropped beyond this
+const MAX_SITES: usize = 4096;
+
+/// Paths removed from each site, by site cache key
+///
+/// Kept outside the site cache so they survive a site being reloaded.
+static TOMBSTONES: once_cell::sync::Lazy<Mutex<HashMap<String, SiteTombstones>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct SiteTombstones {
+    /// Creation time of the newest manifest seen
+    created_at: Timestamp,
+    /// Paths the newest manifest publishes
+    paths: HashSet<String>,
+    /// Paths an earlier manifest published and the newest one doesn't
+    removed: HashSet<String>,
+}
+
+/// Record a manifest loaded for the site `site_key`
+///
+/// Paths the previous manifest published and this one doesn't become tombstones, paths
+/// published again are revived. Manifests older than the newest seen change nothing.
+pub fn observe(site_key: &str, manifest: &Event) {
+    let mut sites = TOMBSTONES.lock().unwrap();
+    if !sites.contains_key(site_key) && sites.len() >= MAX_SITES {
+        let oldest = sites
+            .iter()
+            .min_by_key(|(_, s)| s.created_at)
+            .map(|(k, _)| k.clone());
+        if let Some(oldest) = oldest {
+            sites.remove(&oldest);
+        }
+    }
+    let site = sites.entry(site_key.to_string()).or_default();
+    if manifest.created_at < site.created_at {
+        return;
+    }
+    let paths: HashSet<String> = manifest
+        .tags
+        .filter(TagKind::Custom(Cow::Borrowed("path")))
+        .filter_map(|t| t.content().map(str::to_string))
+        .collect();
+    for path in site.paths.difference(&paths) {
+        if site.removed.len() >= MAX_PATHS {
+            break;
+        }
+        site.removed.insert(path.clone());
+    }
+    site.removed.retain(|p| !paths.contains(p));
+    site.paths = paths;
+    site.created_at = manifest.created_at;
+}
+
+/// Whether `path` was published by an earlier manifest of the site and has since been removed
+pub fn is_removed(site_key: &str, path: &str) -> bool {
+    TOMBSTONES
+        .lock()
+        .unwrap()
+        .get(site_key)
+        .is_some_and(|s| s.removed.contains(path))
+}
+
+/// A route the site published once and has removed since, answered with `410 Gone`
+#[derive(Clone, Debug)]
+pub struct RouteGone {
+    pub path: String,
+}
+
+impl std::fmt::Display for RouteGone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "route {} was removed from the site", self.path)
+    }
+}
+
+impl std::error::Error for RouteGone {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind, Tag};
+
+    fn manifest(keys: &Keys, created_at: u64, paths: &[&str]) -> Event {
+        EventBuilder::new(Kind::Custom(15128), "")
+            .tags(
+                paths
+                    .iter()
+                    .map(|p| Tag::parse(["path", p, &"ab".repeat(32)]).unwrap()),
+            )
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let keys = Keys::generate();
+        let key = keys.public_key().to_hex();
+        observe(
+            &key,
+            &manifest(&keys, 100, &["/index.html", "/old.html", "/draft.html"]),
+        );
+        assert!(!is_removed(&key, "/old.html"));
+
+        observe(&key, &manifest(&keys, 200, &["/index.html", "/draft.html"]));
+        assert!(is_removed(&key, "/old.html"));
+        assert!(!is_removed(&key, "/index.html"));
+        assert!(!is_removed(&key, "/never.html"));
+
+        // A lagging relay's older manifest doesn't revive anything
+        observe(&key, &manifest(&keys, 150, &["/index.html", "/old.html"]));
+        assert!(is_removed(&key, "/old.html"));
+        assert!(!is_removed(&key, "/draft.html"));
+
+        // Publishing a path again revives it
+        observe(&key, &manifest(&keys, 300, &["/index.html", "/old.html"]));
+        assert!(!is_removed(&key, "/old.html"));
+        assert!(is_removed(&key, "/draft.html"));
+        assert!(!is_removed("other", "/draft.html"));
+    }
+}